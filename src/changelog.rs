@@ -0,0 +1,201 @@
+use std::{fmt, path::Path};
+
+use crate::{error::ErrorKind, read_bytes};
+
+const INDEX_ENTRY_SIZE: usize = 64;
+const FLAG_INLINE: u16 = 1;
+const VERSION_NG: u16 = 1;
+
+/// Mercurial changeset node id (sha1).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId([u8; 20]);
+
+impl NodeId {
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.as_bytes();
+        if hex.len() != 40 {
+            return None;
+        }
+        let mut node = [0u8; 20];
+        for (i, byte) in node.iter_mut().enumerate() {
+            let hi = (hex[2 * i] as char).to_digit(16)?;
+            let lo = (hex[2 * i + 1] as char).to_digit(16)?;
+            *byte = (hi * 16 + lo) as u8;
+        }
+        Some(Self(node))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        bytes.try_into().ok().map(Self)
+    }
+
+    pub fn to_hex(self) -> String {
+        self.0.iter().map(|x| format!("{:02x}", x)).collect()
+    }
+
+    /// Whether hex form of node id starts with `prefix`.
+    pub fn starts_with(&self, prefix: &str) -> bool {
+        prefix.len() <= 40
+            && prefix.chars().enumerate().all(|(i, c)| {
+                let byte = self.0[i / 2];
+                let nibble = if i % 2 == 0 { byte >> 4 } else { byte & 0xf };
+                c.to_digit(16) == Some(nibble.into())
+            })
+    }
+
+    /// Short form of node id, same as in `hg log`.
+    pub fn short(self) -> String {
+        self.to_hex()[..12].into()
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::Debug for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NodeId({})", self)
+    }
+}
+
+/// Index of Mercurial changelog (`.hg/store/00changelog.i`).
+///
+/// `hg_parser` does not expose changeset node ids, so index is read here.
+#[derive(Debug)]
+pub struct Changelog {
+    nodes: Vec<NodeId>,
+    /// Nodes with revisions sorted by node, for lookup by node or its prefix.
+    sorted: Vec<(NodeId, usize)>,
+}
+
+impl Changelog {
+    pub fn open<P: AsRef<Path>>(repo_path: P) -> Result<Self, ErrorKind> {
+        let index_path = repo_path
+            .as_ref()
+            .join(".hg")
+            .join("store")
+            .join("00changelog.i");
+        let index = read_bytes(&index_path)?;
+        Self::parse(&index)
+    }
+
+    fn parse(index: &[u8]) -> Result<Self, ErrorKind> {
+        let mut nodes = vec![];
+        if index.is_empty() {
+            return Ok(Self {
+                nodes,
+                sorted: vec![],
+            });
+        }
+        if index.len() < 4 {
+            return Err(ErrorKind::WrongChangelog("truncated header".into()));
+        }
+        let flags = u16::from_be_bytes([index[0], index[1]]);
+        let version = u16::from_be_bytes([index[2], index[3]]);
+        if version != VERSION_NG {
+            return Err(ErrorKind::WrongChangelog(format!(
+                "unsupported revlog version {}",
+                version
+            )));
+        }
+        let inline = flags & FLAG_INLINE != 0;
+
+        let mut offset = 0;
+        while offset + INDEX_ENTRY_SIZE <= index.len() {
+            let entry = &index[offset..offset + INDEX_ENTRY_SIZE];
+            let be_u32 = |at: usize| {
+                u32::from_be_bytes([entry[at], entry[at + 1], entry[at + 2], entry[at + 3]])
+            };
+            let compressed_len = be_u32(8) as usize;
+            let mut node = [0u8; 20];
+            node.copy_from_slice(&entry[32..52]);
+            nodes.push(NodeId(node));
+            offset += INDEX_ENTRY_SIZE;
+            if inline {
+                offset += compressed_len;
+            }
+        }
+
+        let mut sorted: Vec<_> = nodes
+            .iter()
+            .enumerate()
+            .map(|(revision, node)| (*node, revision))
+            .collect();
+        sorted.sort_unstable();
+
+        Ok(Self { nodes, sorted })
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn node(&self, revision: usize) -> Option<NodeId> {
+        self.nodes.get(revision).cloned()
+    }
+
+    pub fn revision(&self, node: &NodeId) -> Option<usize> {
+        self.sorted
+            .binary_search_by_key(node, |(node, _)| *node)
+            .ok()
+            .map(|index| self.sorted[index].1)
+    }
+
+    /// Revision of node with hex prefix, `None` if it is unknown or ambiguous.
+    pub fn revision_by_prefix(&self, prefix: &str) -> Option<usize> {
+        let prefix = prefix.to_ascii_lowercase();
+        let start = self
+            .sorted
+            .partition_point(|(node, _)| node.to_hex() < prefix);
+        let mut found = self.sorted[start..]
+            .iter()
+            .take_while(|(node, _)| node.starts_with(&prefix));
+        match (found.next(), found.next()) {
+            (Some((_, revision)), None) => Some(*revision),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(node: u8, data: &[u8]) -> Vec<u8> {
+        let mut result = vec![0u8; 8];
+        result.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        result.extend_from_slice(&[0u8; 20]);
+        result.extend_from_slice(&[node; 20]);
+        result.extend_from_slice(&[0u8; 12]);
+        result.extend_from_slice(data);
+        result
+    }
+
+    #[test]
+    fn parse_inline_changelog() {
+        let mut index = entry(1, b"first");
+        index[..4].copy_from_slice(&[0, 1, 0, 1]);
+        index.extend(entry(2, b"second"));
+        index.extend(entry(3, b""));
+
+        let changelog = Changelog::parse(&index).unwrap();
+        assert_eq!(changelog.len(), 3);
+        let node = changelog.node(1).unwrap();
+        assert_eq!(node.to_hex(), "02".repeat(20));
+        assert_eq!(node.short(), "020202020202");
+        assert_eq!(changelog.node(3), None);
+        assert_eq!(NodeId::from_hex(&node.to_hex()), Some(node));
+        assert_eq!(NodeId::from_bytes(&node.0), Some(node));
+        assert_eq!(NodeId::from_bytes(b"short"), None);
+        assert_eq!(changelog.revision(&node), Some(1));
+        assert_eq!(changelog.revision_by_prefix("0202020"), Some(1));
+        assert_eq!(changelog.revision_by_prefix("0404040404"), None);
+        assert_eq!(changelog.revision_by_prefix("03"), Some(2));
+        assert_eq!(changelog.revision_by_prefix("0"), None);
+        assert_eq!(changelog.revision(&NodeId([4; 20])), None);
+        assert!(!node.starts_with("0203"));
+    }
+}
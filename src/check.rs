@@ -0,0 +1,595 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    io::Write,
+    path::Path,
+};
+
+use tracing::info;
+
+use crate::config::{BookmarkCollision, RepositoryConfig, UnnamedHeads};
+use crate::env::Environment;
+use crate::error::ErrorKind;
+use crate::git::DEFAULT_BRANCH;
+use crate::{
+    hg_branch_key, largefiles, sanitize_branchname, sanitize_name, to_string, MercurialRepo,
+    HG_DEFAULT_BRANCH,
+};
+
+/// Problems found by `check`, errors fail import, warnings do not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        })
+    }
+}
+
+/// Problem of conversion, `revision` is the first changeset where it is seen.
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub severity: Severity,
+    pub kind: &'static str,
+    pub revision: Option<usize>,
+    pub message: String,
+}
+
+/// All problems found in repository.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    fn add(
+        &mut self,
+        severity: Severity,
+        kind: &'static str,
+        revision: Option<usize>,
+        message: String,
+    ) {
+        self.problems.push(Problem {
+            severity,
+            kind,
+            revision,
+            message,
+        });
+    }
+
+    pub fn errors(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.problems
+            .iter()
+            .filter(|x| x.severity == severity)
+            .count()
+    }
+
+    /// Writes one line per problem.
+    pub fn write_text(&self, output: &mut dyn Write) -> std::io::Result<()> {
+        for problem in &self.problems {
+            write!(output, "{}: {}: ", problem.severity, problem.kind)?;
+            if let Some(revision) = problem.revision {
+                write!(output, "r{}: ", revision)?;
+            }
+            writeln!(output, "{}", problem.message)?;
+        }
+        Ok(())
+    }
+
+    /// Writes JSON object with `errors`, `warnings` counts and `problems` list.
+    pub fn write_json(&self, output: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(output, "{{")?;
+        writeln!(output, "  \"errors\": {},", self.errors())?;
+        writeln!(output, "  \"warnings\": {},", self.warnings())?;
+        write!(output, "  \"problems\": [")?;
+        for (i, problem) in self.problems.iter().enumerate() {
+            if i > 0 {
+                write!(output, ",")?;
+            }
+            write!(
+                output,
+                "\n    {{\"severity\": \"{}\", \"kind\": \"{}\", \"revision\": {}, \"message\": {}}}",
+                problem.severity,
+                problem.kind,
+                problem
+                    .revision
+                    .map_or_else(|| "null".into(), |x| x.to_string()),
+                json_string(&problem.message)
+            )?;
+        }
+        if !self.problems.is_empty() {
+            write!(output, "\n  ")?;
+        }
+        writeln!(output, "]\n}}")?;
+        Ok(())
+    }
+}
+
+/// Walks all changesets of repository without writing anything and reports
+/// every problem of conversion with `config`.
+pub fn check<P: AsRef<Path>>(
+    hg_repo: P,
+    config: &RepositoryConfig,
+    env: &Environment,
+    ignore_unknown_requirements: bool,
+    max_file_size: usize,
+) -> Result<Report, ErrorKind> {
+    let repo = MercurialRepo::open(hg_repo, config, ignore_unknown_requirements, env)?;
+    let default_branch = config.default_branch().unwrap_or(DEFAULT_BRANCH);
+    let mut checker = Checker {
+        repo: &repo,
+        default_branch,
+        report: Report::default(),
+        brmap: config.branches.clone().unwrap_or_default(),
+        users: BTreeMap::new(),
+        branches: BTreeMap::new(),
+        paths: HashSet::new(),
+        live_paths: HashMap::new(),
+    };
+    checker.changesets(max_file_size)?;
+    checker.users();
+    checker.refs()?;
+    checker.heads()?;
+    checker.report.problems.sort_by_key(|x| x.severity);
+    Ok(checker.report)
+}
+
+struct Checker<'r, 'a> {
+    repo: &'r MercurialRepo<'a>,
+    default_branch: &'r str,
+    report: Report,
+    brmap: HashMap<String, String>,
+    /// User to revision where it is seen first and number of changesets.
+    users: BTreeMap<String, (usize, usize)>,
+    /// Mercurial branch to revision where it is seen first.
+    branches: BTreeMap<String, usize>,
+    /// Paths already checked for Windows.
+    paths: HashSet<Vec<u8>>,
+    /// Lowercase path to existing paths, tracked in revision order across branches.
+    live_paths: HashMap<String, HashSet<String>>,
+}
+
+impl Checker<'_, '_> {
+    fn changesets(&mut self, max_file_size: usize) -> Result<(), ErrorKind> {
+        let tip = self.repo.changelog_len()?;
+        info!("Checking {} changesets", tip);
+        for changeset in self.repo.range(0..tip) {
+            let revision = changeset.revision.0 as usize;
+            let header = &changeset.header;
+
+            let user = self.decode(revision, "user", &header.user, Severity::Error);
+            let entry = self.users.entry(user).or_insert((revision, 0));
+            entry.1 += 1;
+
+            let branch = header.extra.get(b"branch".as_slice()).map_or_else(
+                || HG_DEFAULT_BRANCH.into(),
+                |x| self.decode(revision, "branch", x, Severity::Error),
+            );
+            self.branches.entry(branch).or_insert(revision);
+
+            self.decode(revision, "message", &header.comment, Severity::Warning);
+
+            // Deletions go first, so renames which change case only are not collisions.
+            let (deleted, changed): (Vec<_>, Vec<_>) =
+                changeset.files.iter().partition(|x| x.data.is_none());
+            for file in deleted.into_iter().chain(changed) {
+                if self.repo.is_skipped(&file.path) {
+                    continue;
+                }
+                let path = self.repo.git_path(&file.path);
+                let (path, standin) = match largefiles::original_path(&path) {
+                    Some(original) if self.repo.config.largefiles_to_lfs => {
+                        (original.to_vec(), true)
+                    }
+                    _ => (path.into_owned(), false),
+                };
+                let path_str = to_string(&path);
+                let lower = path_str.to_lowercase();
+                let Some(data) = &file.data else {
+                    if let Some(paths) = self.live_paths.get_mut(&lower) {
+                        paths.remove(&path_str);
+                    }
+                    continue;
+                };
+
+                let lfs = self.repo.lfs_patterns.is_match(&path_str)
+                    || self
+                        .repo
+                        .config
+                        .lfs_size_threshold
+                        .is_some_and(|threshold| data.len() > threshold);
+                if data.len() > max_file_size && !standin && !lfs {
+                    self.report.add(
+                        Severity::Warning,
+                        "file size",
+                        Some(revision),
+                        format!("'{}' has {} bytes", path_str, data.len()),
+                    );
+                }
+
+                let paths = self.live_paths.entry(lower).or_default();
+                if !paths.contains(&path_str) {
+                    if let Some(other) = paths.iter().next() {
+                        self.report.add(
+                            Severity::Warning,
+                            "case collision",
+                            Some(revision),
+                            format!("'{}' differs from '{}' only by case", path_str, other),
+                        );
+                    }
+                    paths.insert(path_str.clone());
+                }
+
+                if self.paths.insert(path) {
+                    if let Some(problem) = windows_path_problem(&path_str) {
+                        self.report.add(
+                            Severity::Warning,
+                            "windows path",
+                            Some(revision),
+                            format!("'{}' {}", path_str, problem),
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Text of metadata field, problem is reported if it is not valid UTF-8.
+    fn decode(&mut self, revision: usize, field: &str, bytes: &[u8], severity: Severity) -> String {
+        match self.repo.decoder.decode(bytes) {
+            Ok((text, None)) => text.into_owned(),
+            Ok((text, Some(encoding))) => {
+                self.report.add(
+                    Severity::Warning,
+                    "encoding",
+                    Some(revision),
+                    format!("{} is transcoded from {}", field, encoding.name()),
+                );
+                text.into_owned()
+            }
+            Err(_) => {
+                self.report.add(
+                    severity,
+                    "encoding",
+                    Some(revision),
+                    format!("{} is not valid UTF-8 and no `encodings` are set", field),
+                );
+                to_string(bytes)
+            }
+        }
+    }
+
+    fn users(&mut self) {
+        let authors = self.repo.config.authors.is_some() || self.repo.env.authors.is_some();
+        for (user, (revision, count)) in &self.users {
+            let mapped = self
+                .repo
+                .config
+                .authors
+                .iter()
+                .chain(self.repo.env.authors.iter())
+                .any(|x| x.contains_key(user));
+            if mapped {
+                continue;
+            }
+            if self.repo.fixup_user(user).is_err() {
+                self.report.add(
+                    Severity::Error,
+                    "user",
+                    Some(*revision),
+                    format!("'{}' of {} changesets is malformed", user, count),
+                );
+            } else if authors {
+                self.report.add(
+                    Severity::Warning,
+                    "user",
+                    Some(*revision),
+                    format!("'{}' of {} changesets is not mapped", user, count),
+                );
+            }
+        }
+    }
+
+    /// Branches, bookmarks and tags: invalid Git ref names and collisions.
+    fn refs(&mut self) -> Result<(), ErrorKind> {
+        let repo = self.repo;
+        let config = repo.config;
+        let default_branch = self.default_branch;
+        // Git ref to Mercurial names it is exported from.
+        let mut refs: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+        for (branch, revision) in &self.branches {
+            let git_branch = repo
+                .git_branch(
+                    hg_branch_key(branch, default_branch),
+                    &mut self.brmap,
+                    default_branch,
+                )
+                .clone();
+            if config.bookmarks_only && branch != HG_DEFAULT_BRANCH {
+                continue;
+            }
+            let reference = format!("refs/heads/{}", git_branch);
+            if let Some(problem) = ref_format_problem(&reference) {
+                self.report.add(
+                    Severity::Error,
+                    "branch",
+                    Some(*revision),
+                    format!(
+                        "'{}' gives Git ref '{}' which {}",
+                        branch, reference, problem
+                    ),
+                );
+            }
+            refs.entry(reference)
+                .or_default()
+                .push(format!("branch '{}'", branch));
+        }
+
+        if repo.exports_bookmarks() {
+            for (name, revision) in repo.bookmarks()? {
+                if name == "@" {
+                    continue;
+                }
+                let bookmark = sanitize_branchname(
+                    &name,
+                    config.bookmark_prefix.as_ref(),
+                    repo.env.fix_wrong_branchname,
+                );
+                let reference = format!("refs/heads/{}", bookmark);
+                if let Some(problem) = ref_format_problem(&reference) {
+                    self.report.add(
+                        Severity::Error,
+                        "bookmark",
+                        Some(revision),
+                        format!("'{}' gives Git ref '{}' which {}", name, reference, problem),
+                    );
+                }
+                if let Some(branches) = refs.get(&reference) {
+                    let severity = if config.bookmark_collision == BookmarkCollision::Fail {
+                        Severity::Error
+                    } else {
+                        Severity::Warning
+                    };
+                    self.report.add(
+                        severity,
+                        "name collision",
+                        Some(revision),
+                        format!(
+                            "bookmark '{}' and {} are both exported to '{}'",
+                            name,
+                            branches.join(", "),
+                            reference
+                        ),
+                    );
+                    continue;
+                }
+                refs.entry(reference)
+                    .or_default()
+                    .push(format!("bookmark '{}'", name));
+            }
+        }
+
+        for (name, (revision, _)) in repo.tags()? {
+            let tag = sanitize_name(&name, config.tag_prefix.as_ref(), "tag");
+            let reference = format!("refs/tags/{}", tag);
+            if let Some(problem) = ref_format_problem(&reference) {
+                self.report.add(
+                    Severity::Error,
+                    "tag",
+                    Some(revision),
+                    format!("'{}' gives Git ref '{}' which {}", name, reference, problem),
+                );
+            }
+            refs.entry(reference)
+                .or_default()
+                .push(format!("tag '{}'", name));
+        }
+
+        let mut lowercase: BTreeMap<String, Vec<&String>> = BTreeMap::new();
+        for (reference, names) in &refs {
+            if names.len() > 1 {
+                self.report.add(
+                    Severity::Error,
+                    "name collision",
+                    None,
+                    format!("{} are all exported to '{}'", names.join(", "), reference),
+                );
+            }
+            lowercase
+                .entry(reference.to_lowercase())
+                .or_default()
+                .push(reference);
+        }
+        for references in lowercase.values().filter(|x| x.len() > 1) {
+            let references: Vec<_> = references.iter().map(|x| format!("'{}'", x)).collect();
+            self.report.add(
+                Severity::Warning,
+                "case collision",
+                None,
+                format!("Git refs {} differ only by case", references.join(", ")),
+            );
+        }
+        Ok(())
+    }
+
+    /// Unnamed heads which fail import.
+    fn heads(&mut self) -> Result<(), ErrorKind> {
+        let repo = self.repo;
+        let fail = repo.config.unnamed_heads == UnnamedHeads::Fail;
+        let len = repo.changelog.len();
+        let mut failed = HashSet::new();
+        if !repo.config.allow_unnamed_heads || fail {
+            for (branch, head) in repo.unnamed_heads(len, fail)? {
+                let description = repo.describe_head(&branch, head.revision)?;
+                self.report.add(
+                    Severity::Error,
+                    "unnamed head",
+                    Some(head.revision),
+                    description.trim().to_string(),
+                );
+                failed.insert(head.revision);
+            }
+        }
+        if fail {
+            return Ok(());
+        }
+        let default_branch = self.default_branch;
+        for (branch, head) in repo.unnamed_heads(len, true)? {
+            if failed.contains(&head.revision) {
+                continue;
+            }
+            let description = repo.describe_head(&branch, head.revision)?;
+            let branch = repo.git_branch(
+                hg_branch_key(&branch, default_branch),
+                &mut self.brmap,
+                default_branch,
+            );
+            let Some(reference) = repo.unnamed_head_ref(branch, head.revision)? else {
+                continue;
+            };
+            self.report.add(
+                Severity::Warning,
+                "unnamed head",
+                Some(head.revision),
+                format!("{}, exported as {}", description.trim(), reference),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Why reference is rejected by `git check-ref-format`, `None` if it is valid.
+fn ref_format_problem(reference: &str) -> Option<&'static str> {
+    if reference.ends_with('/') || reference.contains("//") {
+        return Some("has empty path component");
+    }
+    if reference.ends_with('.') {
+        return Some("ends with dot");
+    }
+    if reference.contains("..") {
+        return Some("contains '..'");
+    }
+    if reference.contains("@{") {
+        return Some("contains '@{'");
+    }
+    if reference
+        .chars()
+        .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+    {
+        return Some("contains forbidden character");
+    }
+    if reference
+        .split('/')
+        .any(|x| x.starts_with('.') || x.ends_with(".lock"))
+    {
+        return Some("has component starting with dot or ending with '.lock'");
+    }
+    None
+}
+
+/// Why path cannot be checked out on Windows, `None` if it can.
+fn windows_path_problem(path: &str) -> Option<&'static str> {
+    const RESERVED: [&str; 22] = [
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+    for component in path.split('/') {
+        if component
+            .chars()
+            .any(|c| c.is_ascii_control() || "<>:\"\\|?*".contains(c))
+        {
+            return Some("contains character forbidden on Windows");
+        }
+        if component.ends_with('.') || component.ends_with(' ') {
+            return Some("has component ending with dot or space");
+        }
+        let stem = component.split('.').next().unwrap_or_default().trim_end();
+        if RESERVED.iter().any(|x| x.eq_ignore_ascii_case(stem)) {
+            return Some("has component with name reserved on Windows");
+        }
+    }
+    None
+}
+
+fn json_string(text: &str) -> String {
+    let mut result = String::with_capacity(text.len() + 2);
+    result.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            c if c.is_control() => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn check_names_and_report() {
+        assert_eq!(ref_format_problem("refs/heads/feature/x"), None);
+        assert!(ref_format_problem("refs/heads/my branch").is_some());
+        assert!(ref_format_problem("refs/heads/a..b").is_some());
+        assert!(ref_format_problem("refs/heads/x.lock").is_some());
+        assert!(ref_format_problem("refs/tags/.hidden").is_some());
+        assert!(ref_format_problem("refs/heads/x/").is_some());
+
+        assert_eq!(windows_path_problem("src/main.rs"), None);
+        assert!(windows_path_problem("docs/aux.txt").is_some());
+        assert!(windows_path_problem("Com1").is_some());
+        assert!(windows_path_problem("a/what?").is_some());
+        assert!(windows_path_problem("dir./file").is_some());
+
+        let mut report = Report::default();
+        report.add(
+            Severity::Error,
+            "user",
+            Some(3),
+            "'x\"y' is malformed".into(),
+        );
+        report.add(Severity::Warning, "case collision", None, "a\tb".into());
+        let mut text = vec![];
+        report.write_text(&mut text).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "error: user: r3: 'x\"y' is malformed\nwarning: case collision: a\tb\n"
+        );
+        let mut json = vec![];
+        report.write_json(&mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            r#"{
+  "errors": 1,
+  "warnings": 1,
+  "problems": [
+    {"severity": "error", "kind": "user", "revision": 3, "message": "'x\"y' is malformed"},
+    {"severity": "warning", "kind": "case collision", "revision": null, "message": "a\u0009b"}
+  ]
+}
+"#
+        );
+    }
+}
@@ -39,6 +39,66 @@ pub enum Cli {
         #[structopt(flatten)]
         args: BuildMarksArgs,
     },
+    /// Shows commit messages changed by message_rewrites of config, nothing is imported
+    #[structopt(name = "preview-messages")]
+    PreviewMessages {
+        /// The Mercurial repo which messages are rewritten.
+        #[structopt(parse(from_os_str))]
+        hg_repo: PathBuf,
+        /// Repository configuration in toml format.
+        #[structopt(parse(from_os_str), long, short)]
+        config: PathBuf,
+        /// Ignore unknown requirements.
+        #[structopt(name = "ignore-unknown-requirements", long, short)]
+        ignore_unknown_requirements: bool,
+    },
+    /// Checks all changesets for conversion problems and reports them at once, nothing is imported
+    #[structopt(name = "check")]
+    Check {
+        /// The Mercurial repo to check.
+        #[structopt(parse(from_os_str))]
+        hg_repo: PathBuf,
+        /// Repository configuration in toml format.
+        #[structopt(parse(from_os_str), long, short)]
+        config: Option<PathBuf>,
+        /// Authors remapping in toml format.
+        #[structopt(parse(from_os_str), long, short)]
+        authors: Option<PathBuf>,
+        /// Files larger than this size in bytes are reported.
+        #[structopt(name = "max-file-size", long, default_value = "104857600")]
+        max_file_size: usize,
+        /// Print report in JSON format.
+        #[structopt(long)]
+        json: bool,
+        /// Fix wrong Mercurial branch names (not compatible with git ref format).
+        #[structopt(name = "fix-wrong-branch-names", long)]
+        fix_wrong_branchname: bool,
+        /// Ignore unknown requirements.
+        #[structopt(name = "ignore-unknown-requirements", long, short)]
+        ignore_unknown_requirements: bool,
+    },
+    /// Generates authors mapping and branches table templates with every user and named branch
+    #[structopt(name = "generate-mappings")]
+    GenerateMappings {
+        /// The Mercurial repo to read users and branches from.
+        #[structopt(parse(from_os_str))]
+        hg_repo: PathBuf,
+        /// Repository configuration in toml format, its mappings are pre-filled.
+        #[structopt(parse(from_os_str), long, short)]
+        config: Option<PathBuf>,
+        /// Authors remapping in toml format, its mappings are pre-filled.
+        #[structopt(parse(from_os_str), long, short)]
+        authors: Option<PathBuf>,
+        /// Write authors template to file instead of stdout.
+        #[structopt(parse(from_os_str), name = "authors-output", long)]
+        authors_output: Option<PathBuf>,
+        /// Write branches template to file instead of stdout.
+        #[structopt(parse(from_os_str), name = "branches-output", long)]
+        branches_output: Option<PathBuf>,
+        /// Ignore unknown requirements.
+        #[structopt(name = "ignore-unknown-requirements", long, short)]
+        ignore_unknown_requirements: bool,
+    },
     /// Generates completion scripts for your shell
     #[structopt(
         name = "completions",
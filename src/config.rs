@@ -1,30 +1,149 @@
 use serde;
 use serde::{Deserialize, Serialize};
 
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct RepositoryConfig {
     pub offset: Option<usize>,
     pub authors: Option<HashMap<String, String>>,
     pub branches: Option<HashMap<String, String>>,
-    #[serde(default)]
+    #[serde(default = "default_allow_unnamed_heads")]
     pub allow_unnamed_heads: bool,
+    #[serde(default)]
+    pub unnamed_heads: UnnamedHeads,
     #[serde(skip_deserializing)]
     pub limit_high: Option<usize>,
     pub path_prefix: Option<String>,
     pub branch_prefix: Option<String>,
     pub tag_prefix: Option<String>,
     #[serde(default)]
+    pub delete_removed_tags: bool,
+    #[serde(default)]
+    pub annotated_tags: bool,
+    #[serde(default)]
+    pub export_bookmarks: bool,
+    pub bookmark_prefix: Option<String>,
+    #[serde(default)]
+    pub bookmark_collision: BookmarkCollision,
+    #[serde(default)]
+    pub bookmarks_only: bool,
+    #[serde(default)]
+    pub strip_files: Vec<String>,
+    #[serde(default)]
+    pub drop_empty_commits: bool,
+    #[serde(default)]
+    pub include_paths: Vec<String>,
+    #[serde(default)]
+    pub exclude_paths: Vec<String>,
+    #[serde(default)]
+    pub path_renames: Vec<PathRename>,
+    pub split_subdirectory: Option<String>,
+    #[serde(default)]
+    pub redactions: Vec<Redaction>,
+    #[serde(default)]
+    pub message_rewrites: Vec<MessageRewrite>,
+    #[serde(default)]
+    pub rewrite_hg_hashes: bool,
+    pub notes: Option<NotesConfig>,
+    pub trailers: Option<String>,
+    #[serde(default)]
+    pub provenance: bool,
+    #[serde(default)]
+    pub hg_git: bool,
+    #[serde(default)]
+    pub encodings: Vec<String>,
+    #[serde(default)]
+    pub keep_message_encoding: bool,
+    #[serde(default)]
+    pub hgignore: HgIgnore,
+    pub subrepos: Option<HashMap<String, SubrepoConfig>>,
+    #[serde(default)]
+    pub inline_subrepos: bool,
+    #[serde(default)]
+    pub largefiles_to_lfs: bool,
+    pub largefiles_usercache: Option<PathBuf>,
+    #[serde(default)]
+    pub missing_largefiles: MissingLargefiles,
+    #[serde(default)]
+    pub lfs_patterns: Vec<String>,
+    pub lfs_size_threshold: Option<usize>,
+    #[serde(default)]
     pub prefix_default_branch: bool,
     pub default_branch: Option<String>,
 }
 
+fn default_allow_unnamed_heads() -> bool {
+    true
+}
+
 impl RepositoryConfig {
     pub fn default_branch(&self) -> Option<&str> {
         self.default_branch.as_deref()
     }
+
+    /// Mercurial working copy directory which is compared with Git by `--verify`.
+    pub fn verified_path(&self, repo: &Path) -> PathBuf {
+        match &self.split_subdirectory {
+            Some(subdirectory) => repo.join(subdirectory.trim_matches('/')),
+            None => repo.to_path_buf(),
+        }
+    }
+
+    /// Why `--verify` cannot compare Mercurial working copy with Git, `None` if it can.
+    pub fn verify_unsupported(&self) -> Option<String> {
+        if !self.include_paths.is_empty() {
+            return Some("include_paths cannot be verified".into());
+        }
+        if !self.path_renames.is_empty() {
+            return Some("path_renames cannot be verified".into());
+        }
+        self.exclude_paths
+            .iter()
+            .find(|pattern| !is_name_pattern(pattern))
+            .map(|pattern| {
+                format!(
+                    "exclude_paths pattern '{}' is not a file name and cannot be verified",
+                    pattern
+                )
+            })
+    }
+
+    /// Files which differ between Mercurial working copy and Git by design.
+    pub fn verify_excluded(&self) -> Vec<String> {
+        let mut excluded = self.strip_files.clone();
+        // `diff --exclude` matches names, other patterns are rejected by `verify_unsupported`.
+        excluded.extend(
+            self.exclude_paths
+                .iter()
+                .filter(|pattern| is_name_pattern(pattern))
+                .map(|pattern| pattern.trim_end_matches('/').to_string()),
+        );
+        if self.hgignore != HgIgnore::Keep {
+            excluded.push(".hgignore".into());
+            excluded.push(".gitignore".into());
+        }
+        if self.subrepos.is_some() {
+            excluded.push(".hgsub".into());
+            excluded.push(".hgsubstate".into());
+            excluded.push(".gitmodules".into());
+        }
+        if self.largefiles_to_lfs
+            || !self.lfs_patterns.is_empty()
+            || self.lfs_size_threshold.is_some()
+        {
+            excluded.push(".hglf".into());
+            excluded.push(".gitattributes".into());
+        }
+        excluded
+    }
+}
+
+/// Whether path pattern matches file or directory name at any level.
+fn is_name_pattern(pattern: &str) -> bool {
+    let name = pattern.trim_end_matches('/');
+    !name.contains('/') && !name.contains("**")
 }
 
 impl Default for RepositoryConfig {
@@ -34,16 +153,161 @@ impl Default for RepositoryConfig {
             authors: None,
             branches: None,
             allow_unnamed_heads: true,
+            unnamed_heads: UnnamedHeads::default(),
             limit_high: None,
             path_prefix: None,
             branch_prefix: None,
             tag_prefix: None,
+            delete_removed_tags: false,
+            annotated_tags: false,
+            export_bookmarks: false,
+            bookmark_prefix: None,
+            bookmark_collision: BookmarkCollision::default(),
+            bookmarks_only: false,
+            strip_files: vec![],
+            drop_empty_commits: false,
+            include_paths: vec![],
+            exclude_paths: vec![],
+            path_renames: vec![],
+            split_subdirectory: None,
+            redactions: vec![],
+            message_rewrites: vec![],
+            rewrite_hg_hashes: false,
+            notes: None,
+            trailers: None,
+            provenance: false,
+            hg_git: false,
+            encodings: vec![],
+            keep_message_encoding: false,
+            hgignore: HgIgnore::default(),
+            subrepos: None,
+            inline_subrepos: false,
+            largefiles_to_lfs: false,
+            largefiles_usercache: None,
+            missing_largefiles: MissingLargefiles::default(),
+            lfs_patterns: vec![],
+            lfs_size_threshold: None,
             prefix_default_branch: false,
             default_branch: None,
         }
     }
 }
 
+/// What to do with extra (unnamed) heads of named branch.
+/// Only the latest head of branch is referenced by branch itself.
+#[derive(Debug, Deserialize, Serialize, Default, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum UnnamedHeads {
+    /// Export as `refs/heads/<branch>-head-<shortnode>`.
+    #[default]
+    Branch,
+    /// Export as `refs/hg/heads/<branch>/<shortnode>`.
+    Namespace,
+    /// Export as `refs/tags/<tag_prefix><branch>-head-<shortnode>`.
+    Tag,
+    /// Stop import.
+    Fail,
+}
+
+/// What to do if bookmark and named branch have the same name in Git.
+#[derive(Debug, Deserialize, Serialize, Default, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum BookmarkCollision {
+    /// Keep branch, skip bookmark.
+    #[default]
+    Branch,
+    /// Move branch to bookmark.
+    Bookmark,
+    /// Stop import.
+    Fail,
+}
+
+/// How `.hgignore` is exported.
+#[derive(Debug, Deserialize, Serialize, Default, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum HgIgnore {
+    /// Export `.hgignore` as is.
+    #[default]
+    Keep,
+    /// Convert `.hgignore` to `.gitignore`.
+    Replace,
+    /// Export both `.hgignore` and converted `.gitignore`.
+    Both,
+}
+
+/// What to do if largefile is not found in largefiles store.
+#[derive(Debug, Deserialize, Serialize, Default, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingLargefiles {
+    /// Stop import.
+    #[default]
+    Fail,
+    /// Write placeholder file with largefile hash.
+    Placeholder,
+}
+
+/// Path rewrite rule, file or directory `from` is moved to `to`.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct PathRename {
+    pub from: String,
+    /// Target path, empty moves content of `from` to repository root.
+    pub to: String,
+}
+
+/// Blob rewrite rule, exactly one of `text`, `regex`, `placeholder` or `blobs` is set.
+#[derive(Debug, Deserialize, Serialize, Default, PartialEq)]
+pub struct Redaction {
+    /// Path patterns rule applies to, all paths if empty.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Literal text to replace.
+    pub text: Option<String>,
+    /// Regular expression to replace.
+    pub regex: Option<String>,
+    /// Replacement of `text` or `regex`, `***REMOVED***` by default.
+    pub replacement: Option<String>,
+    /// Content which replaces whole file.
+    pub placeholder: Option<String>,
+    /// Git blob ids (SHA-1) of blobs which are dropped.
+    #[serde(default)]
+    pub blobs: Vec<String>,
+}
+
+/// Commit message rewrite rule, `replacement` may refer to groups of `regex` as `$1`.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct MessageRewrite {
+    pub regex: String,
+    pub replacement: String,
+}
+
+/// Git notes with Mercurial changeset metadata.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct NotesConfig {
+    /// Notes ref, `refs/notes/hg` by default.
+    #[serde(default = "default_notes_reference")]
+    pub reference: String,
+    /// Changeset extras written to note when present.
+    #[serde(default)]
+    pub extras: Vec<String>,
+}
+
+fn default_notes_reference() -> String {
+    "refs/notes/hg".into()
+}
+
+/// Git submodule for Mercurial subrepository, keyed by source from `.hgsub`.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct SubrepoConfig {
+    /// URL written to `.gitmodules`.
+    pub url: String,
+    /// Mercurial subrepository, used to find revisions of pinned nodes.
+    pub path_hg: Option<PathBuf>,
+    /// Git repository subrepository was imported to, its marks give commits of revisions.
+    pub path_git: Option<PathBuf>,
+    /// Offset used for subrepository import.
+    pub offset: Option<usize>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Default, PartialEq)]
 pub struct PathRepositoryConfig {
     pub alias: Option<String>,
@@ -73,6 +337,19 @@ pub enum RepositorySavedState {
     OffsetedRevision(usize, usize),
 }
 
+/// Tags exported on previous runs, saved next to `RepositorySavedState`.
+#[derive(Debug, Deserialize, Serialize, Default, PartialEq)]
+pub struct ExportedTags {
+    /// Mercurial tag name to Mercurial node it points to.
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Paths tracked by Git LFS in generated `.gitattributes`, saved next to `RepositorySavedState`.
+#[derive(Debug, Deserialize, Serialize, Default, PartialEq)]
+pub struct LfsFiles {
+    pub paths: BTreeSet<String>,
+}
+
 #[cfg(test)]
 mod tests {
     use pretty_assertions::assert_eq;
@@ -85,6 +362,45 @@ mod tests {
         assert_eq!(expected, result);
     }
 
+    #[test]
+    fn exported_tags_to_toml() {
+        let expected = "[tags]\n\"1.0\" = \"0123456789abcdef0123456789abcdef01234567\"\n";
+        let result = toml::to_string(&super::ExportedTags {
+            tags: vec![(
+                "1.0".into(),
+                "0123456789abcdef0123456789abcdef01234567".into(),
+            )]
+            .into_iter()
+            .collect(),
+        })
+        .unwrap();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn verify_excluded_names() {
+        let config = super::RepositoryConfig {
+            strip_files: vec!["secret.txt".into()],
+            exclude_paths: vec!["build/".into(), "src/vendor".into(), "*.o".into()],
+            ..Default::default()
+        };
+        assert_eq!(config.verify_excluded(), vec!["secret.txt", "build", "*.o"]);
+        assert!(config.verify_unsupported().unwrap().contains("src/vendor"));
+        let config = super::RepositoryConfig {
+            exclude_paths: vec!["build/".into()],
+            ..Default::default()
+        };
+        assert_eq!(config.verify_unsupported(), None);
+        let config = super::RepositoryConfig {
+            path_renames: vec![super::PathRename {
+                from: "docs".into(),
+                to: "documentation".into(),
+            }],
+            ..Default::default()
+        };
+        assert!(config.verify_unsupported().is_some());
+    }
+
     #[test]
     fn singleconfig_read_from_toml() {
         let src = include_str!("../examples/single.toml");
@@ -97,6 +413,68 @@ mod tests {
                 path_prefix: Some("prefix1".into()),
                 tag_prefix: Some("prefix2-".into()),
                 branch_prefix: Some("prefix3-".into()),
+                export_bookmarks: true,
+                bookmark_prefix: Some("prefix4-".into()),
+                largefiles_usercache: Some("/path/to/largefiles".into()),
+                lfs_patterns: vec!["*.psd".into()],
+                lfs_size_threshold: Some(10485760),
+                strip_files: vec![
+                    ".hgtags".into(),
+                    ".hgsubstate".into(),
+                    ".hgsigs".into(),
+                    ".hg_archival.txt".into(),
+                ],
+                exclude_paths: vec!["vendor/".into(), "*.o".into()],
+                redactions: vec![
+                    super::Redaction {
+                        paths: vec!["*.ini".into()],
+                        text: Some("hunter2".into()),
+                        ..Default::default()
+                    },
+                    super::Redaction {
+                        regex: Some("AKIA[0-9A-Z]{16}".into()),
+                        replacement: Some("<aws key>".into()),
+                        ..Default::default()
+                    },
+                    super::Redaction {
+                        paths: vec!["dumps/".into()],
+                        placeholder: Some("Removed database dump".into()),
+                        ..Default::default()
+                    },
+                    super::Redaction {
+                        blobs: vec!["d97c5eada5d8c52079031eef0107a4430a9617c5".into()],
+                        ..Default::default()
+                    },
+                ],
+                message_rewrites: vec![
+                    super::MessageRewrite {
+                        regex: r"https://bugs\.example\.com/show_bug\.cgi\?id=(\d+)".into(),
+                        replacement: "#$1".into(),
+                    },
+                    super::MessageRewrite {
+                        regex: r"(^|\W)#(\d+)\b".into(),
+                        replacement: "${1}PROJ-$2".into(),
+                    },
+                ],
+                encodings: vec!["cp1251".into(), "latin1".into()],
+                notes: Some(super::NotesConfig {
+                    reference: "refs/notes/hg".into(),
+                    extras: vec![
+                        "source".into(),
+                        "rebase_source".into(),
+                        "amend_source".into(),
+                    ],
+                }),
+                path_renames: vec![
+                    super::PathRename {
+                        from: "src/legacy/".into(),
+                        to: "legacy/".into(),
+                    },
+                    super::PathRename {
+                        from: "docs/".into(),
+                        to: "documentation/".into(),
+                    },
+                ],
                 authors: Some(
                     vec![
                         ("aaa 1".into(), "Bbb <bbb@company.xyz>".into()),
@@ -116,6 +494,19 @@ mod tests {
                     .collect()
                 ),
                 default_branch: Some("main".into()),
+                subrepos: Some(
+                    vec![(
+                        "../lib".into(),
+                        super::SubrepoConfig {
+                            url: "https://git.example.com/lib.git".into(),
+                            path_hg: Some("/path/to/lib".into()),
+                            path_git: Some("/path/to/lib-git".into()),
+                            offset: Some(0),
+                        }
+                    )]
+                    .into_iter()
+                    .collect()
+                ),
                 ..Default::default()
             }
         )
@@ -137,6 +528,7 @@ mod tests {
                         path_git: "001_git".into(),
                         config: super::RepositoryConfig {
                             allow_unnamed_heads: true,
+                            unnamed_heads: super::UnnamedHeads::Namespace,
                             offset: Some(1000),
                             path_prefix: Some("prefix1".into()),
                             tag_prefix: Some("prefix2-".into()),
@@ -0,0 +1,179 @@
+use std::borrow::Cow;
+
+/// Undefined byte in code page tables.
+const UNDEFINED: char = '\0';
+
+/// Windows-1251 characters of bytes `0x80..=0xBF`, bytes from `0xC0` are `А..я`.
+#[rustfmt::skip]
+const WINDOWS_1251: [char; 64] = [
+    'Ђ', 'Ѓ', '‚', 'ѓ', '„', '…', '†', '‡', '€', '‰', 'Љ', '‹', 'Њ', 'Ќ', 'Ћ', 'Џ',
+    'ђ', '‘', '’', '“', '”', '•', '–', '—', UNDEFINED, '™', 'љ', '›', 'њ', 'ќ', 'ћ', 'џ',
+    '\u{a0}', 'Ў', 'ў', 'Ј', '¤', 'Ґ', '¦', '§', 'Ё', '©', 'Є', '«', '¬', '\u{ad}', '®', 'Ї',
+    '°', '±', 'І', 'і', 'ґ', 'µ', '¶', '·', 'ё', '№', 'є', '»', 'ј', 'Ѕ', 'ѕ', 'ї',
+];
+
+/// Windows-1252 characters of bytes `0x80..=0x9F`, other bytes are the same as in Latin-1.
+#[rustfmt::skip]
+const WINDOWS_1252: [char; 32] = [
+    '€', UNDEFINED, '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', UNDEFINED, 'Ž', UNDEFINED,
+    UNDEFINED, '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', UNDEFINED, 'ž', 'Ÿ',
+];
+
+/// Single byte encoding of Mercurial metadata.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Latin1,
+    Windows1251,
+    Windows1252,
+}
+
+impl Encoding {
+    /// Encoding by name used in config, e.g. `cp1251` or `windows-1251`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().replace('_', "-").as_str() {
+            "latin1" | "latin-1" | "iso-8859-1" | "iso8859-1" => Some(Self::Latin1),
+            "cp1251" | "windows-1251" => Some(Self::Windows1251),
+            "cp1252" | "windows-1252" => Some(Self::Windows1252),
+            _ => None,
+        }
+    }
+
+    /// Name of encoding for Git `encoding` header.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Latin1 => "ISO-8859-1",
+            Self::Windows1251 => "windows-1251",
+            Self::Windows1252 => "windows-1252",
+        }
+    }
+
+    fn char(self, byte: u8) -> char {
+        match (self, byte) {
+            (_, 0..=0x7f) | (Self::Latin1, _) => byte.into(),
+            (Self::Windows1251, 0x80..=0xbf) => WINDOWS_1251[usize::from(byte - 0x80)],
+            (Self::Windows1251, _) => char::from_u32(0x410 + u32::from(byte - 0xc0)).unwrap(),
+            (Self::Windows1252, 0x80..=0x9f) => WINDOWS_1252[usize::from(byte - 0x80)],
+            (Self::Windows1252, _) => byte.into(),
+        }
+    }
+
+    /// Text of bytes, undefined bytes are replaced by `U+FFFD`.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        bytes
+            .iter()
+            .map(|&byte| match self.char(byte) {
+                UNDEFINED if byte != 0 => char::REPLACEMENT_CHARACTER,
+                c => c,
+            })
+            .collect()
+    }
+
+    /// Bytes of text, `None` if some character is not in encoding.
+    pub fn encode(self, text: &str) -> Option<Vec<u8>> {
+        text.chars()
+            .map(|c| {
+                if c.is_ascii() {
+                    return Some(c as u8);
+                }
+                (0x80..=0xff).find(|&byte| self.char(byte) == c)
+            })
+            .collect()
+    }
+
+    /// Whether bytes look like text in this encoding: every byte is defined and printable,
+    /// and no word mixes Latin and Cyrillic letters.
+    fn fits(self, bytes: &[u8]) -> bool {
+        let text = self.decode(bytes);
+        let printable = text.chars().all(|c| {
+            c != char::REPLACEMENT_CHARACTER && (!c.is_control() || matches!(c, '\n' | '\r' | '\t'))
+        });
+        printable
+            && text.split(|c: char| !c.is_alphanumeric()).all(|word| {
+                !(word.chars().any(|c| c.is_ascii_alphabetic())
+                    && word.chars().any(|c| ('\u{400}'..='\u{4ff}').contains(&c)))
+            })
+    }
+}
+
+/// Decodes Mercurial metadata which is not valid UTF-8 by configured encodings.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    encodings: Vec<Encoding>,
+}
+
+impl Decoder {
+    /// Decoder trying encodings in order, unknown encoding name is returned as error.
+    pub fn new(names: &[String]) -> Result<Self, String> {
+        let encodings = names
+            .iter()
+            .map(|name| Encoding::from_name(name).ok_or_else(|| name.clone()))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { encodings })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.encodings.is_empty()
+    }
+
+    /// Text of bytes with encoding used to transcode it, `None` for valid UTF-8.
+    /// Bytes are decoded by the first encoding they fit, otherwise by the first one.
+    /// Without encodings UTF-8 error is returned.
+    pub fn decode<'b>(
+        &self,
+        bytes: &'b [u8],
+    ) -> Result<(Cow<'b, str>, Option<Encoding>), std::str::Utf8Error> {
+        let error = match std::str::from_utf8(bytes) {
+            Ok(text) => return Ok((Cow::Borrowed(text), None)),
+            Err(error) => error,
+        };
+        let encoding = self
+            .encodings
+            .iter()
+            .find(|encoding| encoding.fits(bytes))
+            .or_else(|| self.encodings.first())
+            .ok_or(error)?;
+        Ok((Cow::Owned(encoding.decode(bytes)), Some(*encoding)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn decode_legacy_encodings() {
+        let decoder = Decoder::new(&["cp1251".into(), "latin1".into()]).unwrap();
+        let cyrillic = b"\xcf\xf0\xe8\xe2\xe5\xf2, \xb8\xe6 \xb9 1";
+        assert_eq!(
+            decoder.decode(cyrillic).unwrap(),
+            (
+                Cow::Owned("Привет, ёж № 1".into()),
+                Some(Encoding::Windows1251)
+            )
+        );
+        assert_eq!(
+            decoder.decode(b"Caf\xe9 cr\xe8me").unwrap(),
+            (Cow::Owned("Café crème".into()), Some(Encoding::Latin1))
+        );
+        assert_eq!(
+            decoder.decode("Привет".as_bytes()).unwrap(),
+            (Cow::Borrowed("Привет"), None)
+        );
+        assert_eq!(
+            Encoding::Windows1251.encode("Привет, ёж № 1").unwrap(),
+            cyrillic
+        );
+        assert_eq!(Encoding::Latin1.encode("€"), None);
+        assert_eq!(
+            Decoder::new(&["cp1252".into()])
+                .unwrap()
+                .decode(b"\x80 \x81")
+                .unwrap()
+                .0,
+            "€ \u{fffd}"
+        );
+        assert!(Decoder::default().decode(b"\xff").is_err());
+        assert_eq!(Decoder::new(&["koi8-u".into()]).unwrap_err(), "koi8-u");
+    }
+}
@@ -12,8 +12,42 @@ pub enum ErrorKind {
     IO(#[from] std::io::Error),
     #[error("verify error {0}")]
     VerifyFailure(String),
+    #[error("--verify is not supported: {0}")]
+    VerifyUnsupported(String),
     #[error("wrong file data {0}")]
     WrongFileData(String),
+    #[error("wrong changelog {0}")]
+    WrongChangelog(String),
+    #[error(
+        "unnamed heads found in Mercurial repository:
+{0}
+Merge or close extra heads in Mercurial.
+Or set allow_unnamed_heads = true in config to import anyway.
+"
+    )]
+    UnnamedHeads(String),
+    #[error("largefile '{1}' ({2}) of revision {0} is not found in largefiles store")]
+    MissingLargefile(usize, String, String),
+    #[error("wrong Git LFS pattern '{0}'")]
+    WrongLfsPattern(String),
+    #[error("paths '{1}' and '{2}' of revision {0} are both renamed to '{3}'")]
+    PathRenameCollision(usize, String, String, String),
+    #[error("wrong path pattern '{0}'")]
+    WrongPathPattern(String),
+    #[error("wrong redaction rule: {0}")]
+    WrongRedaction(String),
+    #[error("wrong message rewrite regex '{0}'")]
+    WrongMessageRewrite(String),
+    #[error("unknown encoding '{0}'")]
+    WrongEncoding(String),
+    #[error("wrong trailers template: {0}")]
+    WrongTrailers(String),
+    #[error("unexpected response of fast-import '{0}'")]
+    WrongFastImportResponse(String),
+    #[error("repositories {0} have [subrepos], their .gitmodules cannot be merged")]
+    SubreposOfSeveralRepositories(String),
+    #[error("bookmark '{0}' and named branch have the same name '{1}' in Git")]
+    BookmarkCollision(String, String),
     #[error(
         "wrong name of Mercurial user '{0}'.
 Must be in form 'Username <username@email.xyz>'.
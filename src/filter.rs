@@ -0,0 +1,181 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use regex::Regex;
+
+use crate::{config::PathRename, lfs::glob_regex};
+
+/// Include and exclude rules for exported paths. Pattern without slash matches
+/// file or directory name at any level, otherwise path from root; pattern
+/// matching directory matches everything under it.
+#[derive(Debug, Default)]
+pub struct PathFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl PathFilter {
+    /// Compiles rules, wrong pattern is returned as error.
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, String> {
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    /// Path is exported if it matches any include rule (or there are none)
+    /// and does not match any exclude rule.
+    pub fn is_match(&self, path: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|regex| regex.is_match(path)))
+            && !self.exclude.iter().any(|regex| regex.is_match(path))
+    }
+}
+
+fn compile(patterns: &[String]) -> Result<Vec<Regex>, String> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            let glob = pattern.trim_end_matches('/');
+            Regex::new(&format!("{}(?:/|$)", glob_regex(glob))).map_err(|_| pattern.clone())
+        })
+        .collect()
+}
+
+/// Path relative to directory `dir`, `None` if path is outside of it.
+pub fn subdirectory<'p>(dir: &str, path: &'p [u8]) -> Option<&'p [u8]> {
+    path.strip_prefix(dir.trim_matches('/').as_bytes())?
+        .strip_prefix(b"/")
+}
+
+/// Path after first matching rename rule, rule matches file or directory path.
+pub fn rename<'p>(renames: &[PathRename], path: &'p [u8]) -> Cow<'p, [u8]> {
+    for rename in renames {
+        let from = rename.from.trim_matches('/').as_bytes();
+        let rest = if from.is_empty() {
+            path
+        } else {
+            match path.strip_prefix(from) {
+                Some(b"") => b"",
+                Some(rest) if rest.starts_with(b"/") => &rest[1..],
+                _ => continue,
+            }
+        };
+        let to = rename.to.trim_matches('/').as_bytes();
+        return match (to.is_empty(), rest.is_empty()) {
+            (true, _) => Cow::Borrowed(rest),
+            (false, true) => Cow::Owned(to.to_vec()),
+            (false, false) => Cow::Owned([to, b"/", rest].concat()),
+        };
+    }
+    Cow::Borrowed(path)
+}
+
+/// Renamed paths modified by one changeset with their sources, to detect
+/// different files written to one Git path.
+#[derive(Debug, Default)]
+pub struct RenamedPaths {
+    modified: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl RenamedPaths {
+    /// Records modification of `source` written to `target`, other source
+    /// already written to `target` is returned as error.
+    pub fn modify(&mut self, source: &[u8], target: &[u8]) -> Result<(), Vec<u8>> {
+        match self.modified.get(target) {
+            Some(other) if other != source => Err(other.clone()),
+            _ => {
+                self.modified.insert(target.to_vec(), source.to_vec());
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether deletion of `source` is written to `target`: it is not when
+    /// other file is written there, e.g. directory moved to renamed path.
+    pub fn is_deleted(&self, source: &[u8], target: &[u8]) -> bool {
+        self.modified
+            .get(target)
+            .map_or(true, |other| other == source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn include_exclude_paths() {
+        let filter = PathFilter::new(
+            &["src/".into(), "README.md".into()],
+            &["src/vendor".into(), "*.o".into()],
+        )
+        .unwrap();
+        assert!(filter.is_match("src/main.c"));
+        assert!(filter.is_match("README.md"));
+        assert!(filter.is_match("src/README.md"));
+        assert!(!filter.is_match("srcs/main.c"));
+        assert!(!filter.is_match("docs/index.md"));
+        assert!(!filter.is_match("src/vendor/lib.c"));
+        assert!(!filter.is_match("src/main.o"));
+        assert!(filter.is_match("src/vendored.c"));
+
+        let filter = PathFilter::new(&[], &["build".into()]).unwrap();
+        assert!(filter.is_match("src/main.c"));
+        assert!(!filter.is_match("build/out.bin"));
+        assert!(!filter.is_match("sub/build/out.bin"));
+    }
+
+    #[test]
+    fn rename_paths() {
+        let renames = [
+            PathRename {
+                from: "src/legacy/".into(),
+                to: "legacy/".into(),
+            },
+            PathRename {
+                from: "docs".into(),
+                to: "documentation".into(),
+            },
+            PathRename {
+                from: "src".into(),
+                to: "".into(),
+            },
+        ];
+        let rename =
+            |path: &str| String::from_utf8(rename(&renames, path.as_bytes()).into()).unwrap();
+        assert_eq!(rename("src/legacy/old.c"), "legacy/old.c");
+        assert_eq!(rename("src/main.c"), "main.c");
+        assert_eq!(rename("docs/index.md"), "documentation/index.md");
+        assert_eq!(rename("docs"), "documentation");
+        assert_eq!(rename("docs2/index.md"), "docs2/index.md");
+        assert_eq!(rename("README.md"), "README.md");
+
+        assert_eq!(
+            subdirectory("lib/", b"lib/src/a.c"),
+            Some(b"src/a.c".as_slice())
+        );
+        assert_eq!(subdirectory("lib", b"lib"), None);
+        assert_eq!(subdirectory("lib", b"library/a.c"), None);
+    }
+
+    #[test]
+    fn renamed_path_collisions() {
+        // `hg mv docs documentation` with rule `docs/` -> `documentation/`.
+        let mut renamed = RenamedPaths::default();
+        assert!(renamed.is_deleted(b"docs/index.md", b"documentation/index.md"));
+        renamed
+            .modify(b"documentation/index.md", b"documentation/index.md")
+            .unwrap();
+        assert!(!renamed.is_deleted(b"docs/index.md", b"documentation/index.md"));
+        assert!(renamed.is_deleted(b"docs/old.md", b"documentation/old.md"));
+
+        assert_eq!(
+            renamed
+                .modify(b"docs/index.md", b"documentation/index.md")
+                .unwrap_err(),
+            b"documentation/index.md"
+        );
+        renamed
+            .modify(b"documentation/index.md", b"documentation/index.md")
+            .unwrap();
+    }
+}
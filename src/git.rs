@@ -1,16 +1,18 @@
 use std::{
     collections::HashSet,
-    fs::{self, File},
-    io::prelude::Write,
+    fs::{self, File, OpenOptions},
+    io::{prelude::Write, BufReader},
     path::{Path, PathBuf},
     process::{Child, Command, ExitStatus, Stdio},
 };
 
 use super::{
-    config::RepositorySavedState, env::Environment, read_file, TargetRepository,
-    TargetRepositoryError,
+    config::{ExportedTags, LfsFiles, RepositorySavedState},
+    env::Environment,
+    read_file, ImportStart, Responses, TargetRepository, TargetRepositoryError,
 };
 
+use serde::de::DeserializeOwned;
 use tracing::{debug, error, info};
 
 pub const DEFAULT_BRANCH: &str = "master";
@@ -30,8 +32,8 @@ impl<'a> TargetRepository for StdoutTargetRepository<'a> {
         &mut self,
         _git_active_branches: Option<usize>,
         _default_branch: Option<&str>,
-    ) -> Result<(&mut dyn Write, Option<RepositorySavedState>, String), TargetRepositoryError> {
-        Ok((&mut self.stdoutlock, None, DEFAULT_BRANCH.to_string()))
+    ) -> Result<ImportStart<'_>, TargetRepositoryError> {
+        Ok((&mut self.stdoutlock, None, None, DEFAULT_BRANCH.to_string()))
     }
     fn finish(&mut self) -> Result<(), TargetRepositoryError> {
         Ok(())
@@ -63,10 +65,12 @@ impl<'a> GitTargetRepository<'a> {
         &self.path
     }
 
-    fn get_saved_state_path(&self) -> PathBuf {
-        let mut saved_state = self.path.join(".git").join(env!("CARGO_PKG_NAME"));
-        saved_state.set_extension("lock");
-        saved_state
+    /// Path of file with import state or report next to Git repository data,
+    /// e.g. `.git/hg-git-fast-import.tags` for `tags` extension.
+    fn get_state_path(&self, extension: &str) -> PathBuf {
+        let mut path = self.path.join(".git").join(env!("CARGO_PKG_NAME"));
+        path.set_extension(extension);
+        path
     }
 
     pub fn create_repo(&self, default_branch: &str) -> Result<(), TargetRepositoryError> {
@@ -154,7 +158,7 @@ impl<'a> TargetRepository for GitTargetRepository<'a> {
         &mut self,
         git_active_branches: Option<usize>,
         default_branch: Option<&str>,
-    ) -> Result<(&mut dyn Write, Option<RepositorySavedState>, String), TargetRepositoryError> {
+    ) -> Result<ImportStart<'_>, TargetRepositoryError> {
         let path = &self.path;
         let saved_state;
         info!("Checking Git repo: {}", path.to_str().unwrap());
@@ -174,15 +178,13 @@ impl<'a> TargetRepository for GitTargetRepository<'a> {
             if path.is_dir() {
                 info!("Path exists, checking for saved state");
 
-                let saved_state_path = self.get_saved_state_path();
+                let saved_state_path = self.get_state_path("lock");
 
                 if !saved_state_path.exists() {
                     return Err(TargetRepositoryError::SavedStateDoesNotExist);
                 }
 
-                let saved_state_str = read_file(&saved_state_path)?;
-                let loaded_saved_state: RepositorySavedState =
-                    toml::from_str(&saved_state_str).unwrap();
+                let loaded_saved_state: RepositorySavedState = read_state_file(&saved_state_path)?;
 
                 info!("Loaded saved state: {:?}", loaded_saved_state);
                 saved_state = Some(loaded_saved_state);
@@ -205,13 +207,22 @@ impl<'a> TargetRepository for GitTargetRepository<'a> {
         if let Some(git_active_branches) = git_active_branches {
             git_cmd = git_cmd.arg(format!("--active-branches={}", git_active_branches));
         }
-        self.fast_import_cmd = Some(git_cmd.current_dir(path).stdin(Stdio::piped()).spawn()?);
+        // Responses to `get-mark` are written to stdout.
+        let fast_import = self.fast_import_cmd.insert(
+            git_cmd
+                .current_dir(path)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()?,
+        );
+        let responses = fast_import
+            .stdout
+            .take()
+            .map(|stdout| Box::new(BufReader::new(stdout)) as Responses);
 
         Ok((
-            self.fast_import_cmd
-                .as_mut()
-                .map(|x| x.stdin.as_mut().unwrap())
-                .unwrap(),
+            fast_import.stdin.as_mut().unwrap(),
+            responses,
             saved_state,
             default_branch,
         ))
@@ -295,6 +306,7 @@ impl<'a> TargetRepository for GitTargetRepository<'a> {
         &self,
         verified_repo: &str,
         subfolder: Option<&str>,
+        excluded: &[String],
     ) -> Result<(), TargetRepositoryError> {
         info!("Verifying...");
 
@@ -316,9 +328,9 @@ impl<'a> TargetRepository for GitTargetRepository<'a> {
                 "--exclude=*.iml",
                 "--exclude=target",
                 "--exclude=.hgtags",
-                verified_repo,
-                &path,
             ])
+            .args(excluded.iter().map(|file| format!("--exclude={}", file)))
+            .args([verified_repo, &path])
             .status()
             .unwrap();
         if status.success() {
@@ -335,13 +347,75 @@ impl<'a> TargetRepository for GitTargetRepository<'a> {
     fn save_state(&self, state: RepositorySavedState) -> Result<(), TargetRepositoryError> {
         let path = &self.path;
         info!("Saving state to Git repo: {}", path.to_str().unwrap());
-        let saved_state_path = self.get_saved_state_path();
+        let saved_state_path = self.get_state_path("lock");
         let toml = toml::to_string(&state).unwrap();
         let mut f = File::create(saved_state_path)?;
         f.write_all(toml.as_bytes())?;
         Ok(())
     }
 
+    fn lfs_objects_path(&self) -> Option<PathBuf> {
+        Some(self.path.join(".git").join("lfs").join("objects"))
+    }
+
+    fn save_exported_tags(&self, tags: &ExportedTags) -> Result<(), TargetRepositoryError> {
+        info!("Saving exported tags");
+        let toml = toml::to_string(tags).unwrap();
+        let mut f = File::create(self.get_state_path("tags"))?;
+        f.write_all(toml.as_bytes())?;
+        Ok(())
+    }
+
+    fn get_exported_tags(&self) -> Result<Option<ExportedTags>, TargetRepositoryError> {
+        let exported_tags_path = self.get_state_path("tags");
+        if !exported_tags_path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(read_state_file(&exported_tags_path)?))
+    }
+
+    fn save_lfs_files(&self, files: &LfsFiles) -> Result<(), TargetRepositoryError> {
+        info!("Saving Git LFS files");
+        let toml = toml::to_string(files).unwrap();
+        let mut f = File::create(self.get_state_path("lfs"))?;
+        f.write_all(toml.as_bytes())?;
+        Ok(())
+    }
+
+    fn get_lfs_files(&self) -> Result<Option<LfsFiles>, TargetRepositoryError> {
+        let lfs_files_path = self.get_state_path("lfs");
+        if !lfs_files_path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(read_state_file(&lfs_files_path)?))
+    }
+
+    fn reference_exists(&self, reference: &str) -> Result<bool, TargetRepositoryError> {
+        let clean = self.env.map(|x| x.clean).unwrap_or_default();
+        if clean || !self.path.exists() {
+            return Ok(false);
+        }
+        let output = self
+            .git_cmd(&["rev-parse", "--verify", "--quiet", reference])
+            .output()?;
+        Ok(output.status.success())
+    }
+
+    fn save_redaction_report(&self, report: &[String]) -> Result<(), TargetRepositoryError> {
+        info!("Saving redaction report");
+        append_lines(self.get_state_path("redactions"), report)
+    }
+
+    fn save_encoding_report(&self, report: &[String]) -> Result<(), TargetRepositoryError> {
+        info!("Saving encoding report");
+        append_lines(self.get_state_path("encodings"), report)
+    }
+
+    fn save_hg_git_report(&self, report: &[String]) -> Result<(), TargetRepositoryError> {
+        info!("Saving hg-git report");
+        append_lines(self.get_state_path("hg-git"), report)
+    }
+
     fn remote_list(&self) -> Result<HashSet<String>, TargetRepositoryError> {
         debug!("git remote");
         let output = Command::new("git")
@@ -407,3 +481,18 @@ impl<'a> TargetRepository for GitTargetRepository<'a> {
         Ok(())
     }
 }
+
+/// Reads TOML state file, content which cannot be parsed is an error.
+fn read_state_file<T: DeserializeOwned>(path: &Path) -> Result<T, TargetRepositoryError> {
+    toml::from_str(&read_file(path)?)
+        .map_err(|error| TargetRepositoryError::WrongStateFile(path.into(), error.to_string()))
+}
+
+/// Appends lines to report file, creating it if missing.
+fn append_lines(path: PathBuf, lines: &[String]) -> Result<(), TargetRepositoryError> {
+    let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+    for line in lines {
+        writeln!(f, "{}", line)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,269 @@
+/// Result of `.hgignore` conversion.
+#[derive(Debug, PartialEq)]
+pub struct GitIgnore {
+    pub content: Vec<u8>,
+    /// Patterns which have no `.gitignore` equivalent.
+    pub untranslated: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Syntax {
+    Glob,
+    RootGlob,
+    Regexp,
+}
+
+/// Translates `.hgignore` to `.gitignore`.
+///
+/// Untranslated patterns are kept as comments, so they can be fixed by hand.
+pub fn convert(hgignore: &[u8]) -> GitIgnore {
+    let mut result = GitIgnore {
+        content: vec![],
+        untranslated: vec![],
+    };
+    let mut syntax = Syntax::Regexp;
+    for line in String::from_utf8_lossy(hgignore).lines() {
+        let line = strip_comment(line);
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix("syntax:") {
+            match parse_syntax(name.trim()) {
+                Some(value) => syntax = value,
+                None => result.untranslated.push(line.into()),
+            }
+            continue;
+        }
+
+        let (line_syntax, pattern) = match line.split_once(':') {
+            Some((name, pattern)) => match parse_syntax(name) {
+                Some(value) => (value, pattern),
+                None if ["include", "subinclude"].contains(&name) => {
+                    result.untranslated.push(line.into());
+                    add_line(&mut result.content, &format!("# {}", line));
+                    continue;
+                }
+                None => (syntax, line),
+            },
+            None => (syntax, line),
+        };
+
+        let translated = match line_syntax {
+            Syntax::Glob => translate_glob(pattern),
+            Syntax::RootGlob => expand_braces(pattern)
+                .map(|globs| globs.into_iter().map(|glob| format!("/{}", glob)).collect()),
+            Syntax::Regexp => translate_regexp(pattern).map(|glob| vec![glob]),
+        };
+
+        match translated {
+            Some(patterns) => {
+                for pattern in patterns {
+                    add_line(&mut result.content, &escape_line(&pattern));
+                }
+            }
+            None => {
+                result.untranslated.push(line.into());
+                add_line(&mut result.content, &format!("# {}", line));
+            }
+        }
+    }
+    result
+}
+
+fn add_line(content: &mut Vec<u8>, line: &str) {
+    content.extend_from_slice(line.as_bytes());
+    content.push(b'\n');
+}
+
+fn parse_syntax(name: &str) -> Option<Syntax> {
+    match name {
+        "glob" | "relglob" => Some(Syntax::Glob),
+        "rootglob" => Some(Syntax::RootGlob),
+        "re" | "regexp" | "relre" => Some(Syntax::Regexp),
+        _ => None,
+    }
+}
+
+/// Strips comment, `\#` is literal `#`.
+fn strip_comment(line: &str) -> String {
+    let mut result = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'#') => {
+                result.push('#');
+                chars.next();
+            }
+            '#' => break,
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Escapes characters which are special at line start in `.gitignore`.
+fn escape_line(pattern: &str) -> String {
+    if pattern.starts_with('!') || pattern.starts_with('#') {
+        format!("\\{}", pattern)
+    } else {
+        pattern.into()
+    }
+}
+
+/// Mercurial glob matches at any directory level, Git one only if it has no slash.
+fn translate_glob(glob: &str) -> Option<Vec<String>> {
+    Some(
+        expand_braces(glob)?
+            .into_iter()
+            .map(|glob| {
+                if glob.trim_end_matches('/').contains('/') && !glob.starts_with("**/") {
+                    format!("**/{}", glob)
+                } else {
+                    glob
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Expands `{a,b}` alternatives which are not supported by Git. Nested braces are not expanded.
+fn expand_braces(glob: &str) -> Option<Vec<String>> {
+    let start = match glob.find('{') {
+        Some(start) => start,
+        None => return Some(vec![glob.into()]),
+    };
+    let end = start + glob[start..].find('}')?;
+    let alternatives = &glob[start + 1..end];
+    if alternatives.contains('{') {
+        return None;
+    }
+    let mut result = vec![];
+    for alternative in alternatives.split(',') {
+        let expanded = format!("{}{}{}", &glob[..start], alternative, &glob[end + 1..]);
+        result.extend(expand_braces(&expanded)?);
+    }
+    Some(result)
+}
+
+/// Translates simple regular expressions: literals, `.`, `.*`, `.+`, character classes
+/// and `^`, `$` anchors. Mercurial regexp is not rooted without `^`.
+fn translate_regexp(regexp: &str) -> Option<String> {
+    let (rooted, regexp) = match regexp.strip_prefix('^') {
+        Some(regexp) => (true, regexp),
+        None => (false, regexp.strip_prefix(".*").unwrap_or(regexp)),
+    };
+    let (ends, regexp) = match regexp.strip_suffix('$') {
+        Some(regexp) if !regexp.ends_with('\\') => (true, regexp),
+        _ => (false, regexp),
+    };
+
+    let mut glob = String::new();
+    let mut chars = regexp.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '.' => match chars.peek() {
+                Some('*') => {
+                    chars.next();
+                    glob.push('*');
+                }
+                Some('+') => {
+                    chars.next();
+                    glob.push_str("?*");
+                }
+                _ => glob.push('?'),
+            },
+            '\\' => match chars.next() {
+                Some(c) if !c.is_alphanumeric() => push_literal(&mut glob, c),
+                _ => return None,
+            },
+            '[' => {
+                glob.push('[');
+                if chars.peek() == Some(&'^') {
+                    chars.next();
+                    glob.push('!');
+                }
+                loop {
+                    match chars.next()? {
+                        ']' => break,
+                        '\\' => return None,
+                        c => glob.push(c),
+                    }
+                }
+                glob.push(']');
+            }
+            '(' | ')' | '|' | '+' | '?' | '*' | '{' | '}' | '^' | '$' => return None,
+            c => push_literal(&mut glob, c),
+        }
+    }
+    if glob.is_empty() {
+        return None;
+    }
+
+    if !ends && !glob.ends_with('*') {
+        glob.push('*');
+    }
+    if rooted {
+        glob.insert(0, '/');
+    } else {
+        if !glob.starts_with('*') {
+            glob.insert(0, '*');
+        }
+        if glob.trim_end_matches('/').contains('/') {
+            glob.insert_str(0, "**/");
+        }
+    }
+    Some(glob)
+}
+
+fn push_literal(glob: &mut String, c: char) {
+    if "*?[\\".contains(c) {
+        glob.push('\\');
+    }
+    glob.push(c);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn convert_hgignore() {
+        let hgignore = br"
+# comment
+\.orig$
+^target/
+.*\.py[co]$
+\.(rej|bak)$
+
+syntax: glob
+*.swp # editor
+build/output
+{foo,bar}.log
+rootglob:docs/_build
+re:^dist$
+include:other
+";
+        let result = convert(hgignore);
+        assert_eq!(
+            String::from_utf8(result.content).unwrap(),
+            "*.orig\n\
+             /target/*\n\
+             *.py[co]\n\
+             # \\.(rej|bak)$\n\
+             *.swp\n\
+             **/build/output\n\
+             foo.log\n\
+             bar.log\n\
+             /docs/_build\n\
+             /dist\n\
+             # include:other\n"
+        );
+        assert_eq!(
+            result.untranslated,
+            vec!["\\.(rej|bak)$".to_string(), "include:other".into()]
+        );
+    }
+}
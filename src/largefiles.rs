@@ -0,0 +1,51 @@
+use std::{
+    env,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use crate::{read_bytes, to_str};
+
+const STANDIN_DIR: &[u8] = b".hglf/";
+
+/// Path of largefile for standin in `.hglf`.
+pub fn original_path(standin: &[u8]) -> Option<&[u8]> {
+    standin.strip_prefix(STANDIN_DIR)
+}
+
+/// Largefile hash (sha1) from standin content.
+pub fn standin_hash(standin: &[u8]) -> String {
+    to_str(standin).trim().into()
+}
+
+/// Default user cache of largefiles extension.
+pub fn default_usercache() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("LOCALAPPDATA")
+            .or_else(|| env::var_os("APPDATA"))
+            .map(|x| PathBuf::from(x).join("largefiles"))
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|x| PathBuf::from(x).join("Library/Caches/largefiles"))
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|x| PathBuf::from(x).join(".cache")))
+            .map(|x| x.join("largefiles"))
+    }
+}
+
+/// Reads largefile from `.hg/largefiles` of repository or from user cache.
+pub fn read(repo: &Path, usercache: Option<&Path>, hash: &str) -> io::Result<Option<Vec<u8>>> {
+    if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+    let local = repo.join(".hg").join("largefiles");
+    for store in std::iter::once(local.as_path()).chain(usercache) {
+        match read_bytes(store.join(hash)) {
+            Ok(data) => return Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
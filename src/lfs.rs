@@ -0,0 +1,179 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use regex::Regex;
+use sha2::{Digest, Sha256};
+
+/// Glob patterns of files tracked by Git LFS, with `.gitattributes` semantics:
+/// pattern without slash matches file name at any level, otherwise path from root.
+#[derive(Debug, Default)]
+pub struct Patterns {
+    patterns: Vec<(String, Regex)>,
+}
+
+impl Patterns {
+    /// Compiles patterns, wrong pattern is returned as error.
+    pub fn new(patterns: &[String]) -> Result<Self, String> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(&format!("{}$", glob_regex(pattern)))
+                    .map(|regex| (pattern.clone(), regex))
+                    .map_err(|_| pattern.clone())
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { patterns })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn is_match(&self, path: &str) -> bool {
+        self.patterns.iter().any(|(_, regex)| regex.is_match(path))
+    }
+
+    /// `.gitattributes` lines for patterns.
+    pub fn attributes(&self) -> String {
+        self.patterns
+            .iter()
+            .map(|(pattern, _)| attributes(pattern))
+            .collect()
+    }
+}
+
+/// Regex for glob, anchored at start only.
+pub fn glob_regex(glob: &str) -> String {
+    let rooted = glob.contains('/');
+    let glob = glob.strip_prefix('/').unwrap_or(glob);
+    let mut result = String::from(if rooted { "^" } else { "(?:^|/)" });
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    result.push_str("(?:.*/)?");
+                } else {
+                    result.push_str(".*");
+                }
+            }
+            '*' => result.push_str("[^/]*"),
+            '?' => result.push_str("[^/]"),
+            '[' => {
+                result.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    result.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        result.push('\\');
+                    }
+                    result.push(c);
+                }
+                result.push(']');
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    result.push_str(&regex::escape(&c.to_string()));
+                }
+            }
+            c => result.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    result
+}
+
+/// Git LFS pointer file for content and its object id.
+pub fn pointer(data: &[u8]) -> (String, Vec<u8>) {
+    let oid: String = Sha256::digest(data)
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect();
+    let pointer = format!(
+        "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+        oid,
+        data.len()
+    );
+    (oid, pointer.into_bytes())
+}
+
+/// Stores object in `.git/lfs/objects` layout, existing objects are not rewritten.
+pub fn store(objects: &Path, oid: &str, data: &[u8]) -> io::Result<()> {
+    let dir = objects.join(&oid[0..2]).join(&oid[2..4]);
+    let path = dir.join(oid);
+    if path.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(&dir)?;
+    let temp = dir.join(format!("{}.tmp", oid));
+    fs::File::create(&temp)?.write_all(data)?;
+    fs::rename(temp, path)
+}
+
+/// `.gitattributes` line for path or pattern tracked by Git LFS.
+pub fn attributes(pattern: &str) -> String {
+    format!("{} filter=lfs diff=lfs merge=lfs -text\n", pattern)
+}
+
+/// Pattern for exact path from repository root, same escaping as `git lfs track` does.
+pub fn path_pattern(path: &str) -> String {
+    let mut result = String::from("/");
+    for c in path.chars() {
+        match c {
+            ' ' => result.push_str("[[:space:]]"),
+            '*' | '?' | '[' | '\\' | '#' | '!' => {
+                result.push('\\');
+                result.push(c);
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn lfs_pointer() {
+        let (oid, pointer) = pointer(b"large\n");
+        assert_eq!(
+            oid,
+            "9bfce334a37bd1bc1d36b0370195b31fe9a9389dd43d0873891a3544ef1140a1"
+        );
+        assert_eq!(
+            String::from_utf8(pointer).unwrap(),
+            format!(
+                "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 6\n",
+                oid
+            )
+        );
+        let patterns = Patterns::new(&["*.psd".into(), "assets/**/*.bin".into()]).unwrap();
+        assert!(patterns.is_match("a.psd"));
+        assert!(patterns.is_match("dir/b.psd"));
+        assert!(!patterns.is_match("a.psd.txt"));
+        assert!(patterns.is_match("assets/x.bin"));
+        assert!(patterns.is_match("assets/x/y/z.bin"));
+        assert!(!patterns.is_match("dir/assets/x.bin"));
+        assert_eq!(
+            patterns.attributes(),
+            "*.psd filter=lfs diff=lfs merge=lfs -text\n\
+             assets/**/*.bin filter=lfs diff=lfs merge=lfs -text\n"
+        );
+        assert_eq!(
+            attributes(&path_pattern("assets/big file[1].bin")),
+            "/assets/big[[:space:]]file\\[1].bin filter=lfs diff=lfs merge=lfs -text\n"
+        );
+    }
+}
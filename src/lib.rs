@@ -1,37 +1,53 @@
 #[doc = include_str!("../README.md")]
 use std::{
     borrow::Cow,
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fs::File,
     io::{
         self,
-        prelude::{Read, Write},
+        prelude::{BufRead, Read, Write},
     },
     ops::Range,
     path::{Path, PathBuf},
     process::{Command, ExitStatus},
+    rc::Rc,
 };
 
 use lazy_static::lazy_static;
 use regex::Regex;
-use tracing::{info, trace};
+use tracing::{info, trace, warn};
 
 use ordered_parallel_iterator::OrderedParallelIterator;
 
+mod changelog;
+pub mod check;
 pub mod config;
+mod encoding;
 pub mod env;
 pub mod error;
+mod filter;
 pub mod git;
+mod hgignore;
+mod largefiles;
+mod lfs;
+mod message;
 pub mod multi;
+mod redact;
 pub mod single;
+mod subrepo;
 pub mod tools;
 
-use self::config::RepositorySavedState;
+use self::changelog::{Changelog, NodeId};
+use self::config::{
+    BookmarkCollision, ExportedTags, HgIgnore, LfsFiles, MissingLargefiles, RepositorySavedState,
+    UnnamedHeads,
+};
+use self::subrepo::{InlineSubrepo, Source, SubrepoCommits, SubrepoTree, Subrepos};
 pub use error::ErrorKind;
 
 use hg_parser::{
-    file_content, Changeset, FileType, ManifestEntryDetails, MercurialRepository,
-    MercurialRepositoryOptions, Revision, SharedMercurialRepository,
+    file_content, Changeset, ChangesetFile, ChangesetHeader, FileType, ManifestEntryDetails,
+    MercurialRepository, MercurialRepositoryOptions, Revision, SharedMercurialRepository,
 };
 
 pub fn read_file(filename: impl AsRef<Path>) -> io::Result<String> {
@@ -41,6 +57,13 @@ pub fn read_file(filename: impl AsRef<Path>) -> io::Result<String> {
     Ok(buf)
 }
 
+pub fn read_bytes(filename: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let mut file = File::open(filename)?;
+    let mut buf = vec![];
+    file.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
 fn to_str(bytes: &[u8]) -> Cow<'_, str> {
     String::from_utf8_lossy(bytes)
 }
@@ -69,6 +92,8 @@ pub enum TargetRepositoryError {
     IOError(std::io::Error),
     #[error("verification failed")]
     VerifyFail,
+    #[error("wrong state file {0:?}: {1}")]
+    WrongStateFile(PathBuf, String),
 }
 
 impl From<std::io::Error> for TargetRepositoryError {
@@ -77,12 +102,24 @@ impl From<std::io::Error> for TargetRepositoryError {
     }
 }
 
+/// Reader of fast-import responses to `get-mark` commands.
+pub type Responses = Box<dyn BufRead>;
+
+/// Stream for fast-import commands, reader of its responses if target gives them,
+/// saved state and default branch.
+pub type ImportStart<'a> = (
+    &'a mut dyn Write,
+    Option<Responses>,
+    Option<config::RepositorySavedState>,
+    String,
+);
+
 pub trait TargetRepository {
     fn start_import(
         &mut self,
         git_active_branches: Option<usize>,
         default_branch: Option<&str>,
-    ) -> Result<(&mut dyn Write, Option<config::RepositorySavedState>, String), TargetRepositoryError>;
+    ) -> Result<ImportStart<'_>, TargetRepositoryError>;
 
     fn finish(&mut self) -> Result<(), TargetRepositoryError>;
 
@@ -90,6 +127,7 @@ pub trait TargetRepository {
         &self,
         _verified_repo: &str,
         _subfolder: Option<&str>,
+        _excluded: &[String],
     ) -> Result<(), TargetRepositoryError> {
         Ok(())
     }
@@ -102,6 +140,47 @@ pub trait TargetRepository {
         None
     }
 
+    fn save_exported_tags(&self, _tags: &ExportedTags) -> Result<(), TargetRepositoryError> {
+        Ok(())
+    }
+
+    fn get_exported_tags(&self) -> Result<Option<ExportedTags>, TargetRepositoryError> {
+        Ok(None)
+    }
+
+    fn save_lfs_files(&self, _files: &LfsFiles) -> Result<(), TargetRepositoryError> {
+        Ok(())
+    }
+
+    fn get_lfs_files(&self) -> Result<Option<LfsFiles>, TargetRepositoryError> {
+        Ok(None)
+    }
+
+    /// Appends report of blobs changed by redaction rules.
+    fn save_redaction_report(&self, _report: &[String]) -> Result<(), TargetRepositoryError> {
+        Ok(())
+    }
+
+    /// Appends report of hg-git changesets exported to other Git commits.
+    fn save_hg_git_report(&self, _report: &[String]) -> Result<(), TargetRepositoryError> {
+        Ok(())
+    }
+
+    /// Appends report of changesets with metadata transcoded to UTF-8.
+    fn save_encoding_report(&self, _report: &[String]) -> Result<(), TargetRepositoryError> {
+        Ok(())
+    }
+
+    /// Whether reference exists in target before import.
+    fn reference_exists(&self, _reference: &str) -> Result<bool, TargetRepositoryError> {
+        Ok(false)
+    }
+
+    /// Where Git LFS objects are stored, `None` if target has no LFS storage.
+    fn lfs_objects_path(&self) -> Option<PathBuf> {
+        None
+    }
+
     fn remote_list(&self) -> Result<HashSet<String>, TargetRepositoryError> {
         unimplemented!();
     }
@@ -132,6 +211,17 @@ pub enum SourceRepositoryError {
 struct MercurialRepo<'a> {
     path: PathBuf,
     inner: SharedMercurialRepository,
+    changelog: Changelog,
+    lfs_patterns: lfs::Patterns,
+    path_filter: filter::PathFilter,
+    redactions: redact::Rules,
+    message_rewrites: message::Rewrites,
+    trailers: Option<message::Trailers>,
+    decoder: encoding::Decoder,
+    /// Git commits of changesets converted by hg-git, from `.hg/git-mapfile`.
+    git_commits: HashMap<NodeId, String>,
+    /// Repository is opened despite requirements unknown to `hg_parser`.
+    ignore_unknown_requirements: bool,
     config: &'a config::RepositoryConfig,
     env: &'a env::Environment,
 }
@@ -144,14 +234,38 @@ impl<'a> MercurialRepo<'a> {
         ignore_unknown_requirements: bool,
         env: &'a env::Environment,
     ) -> Result<MercurialRepo<'a>, ErrorKind> {
+        // `largefiles` requirement is not known to hg_parser.
+        let ignore_unknown_requirements = ignore_unknown_requirements || config.largefiles_to_lfs;
         Ok(Self {
             path: path.as_ref().to_path_buf(),
             inner: SharedMercurialRepository::new(MercurialRepository::open_with_options(
-                path,
+                path.as_ref(),
                 MercurialRepositoryOptions {
                     ignore_unknown_requirements,
                 },
             )?),
+            changelog: Changelog::open(path.as_ref())?,
+            lfs_patterns: lfs::Patterns::new(&config.lfs_patterns)
+                .map_err(ErrorKind::WrongLfsPattern)?,
+            path_filter: filter::PathFilter::new(&config.include_paths, &config.exclude_paths)
+                .map_err(ErrorKind::WrongPathPattern)?,
+            redactions: redact::Rules::new(&config.redactions)
+                .map_err(ErrorKind::WrongRedaction)?,
+            message_rewrites: message::Rewrites::new(&config.message_rewrites)
+                .map_err(ErrorKind::WrongMessageRewrite)?,
+            trailers: config
+                .trailers
+                .as_deref()
+                .map(message::Trailers::new)
+                .transpose()
+                .map_err(ErrorKind::WrongTrailers)?,
+            decoder: encoding::Decoder::new(&config.encodings).map_err(ErrorKind::WrongEncoding)?,
+            git_commits: if config.hg_git {
+                load_git_mapfile(path.as_ref())?
+            } else {
+                HashMap::new()
+            },
+            ignore_unknown_requirements,
             config,
             env,
         })
@@ -190,8 +304,297 @@ impl<'a> MercurialRepo<'a> {
         self.path.as_path()
     }
 
-    fn verify_heads(&self, _allow_unnamed_heads: bool) -> Result<bool, ErrorKind> {
-        Ok(true)
+    /// Fails with report of unnamed heads if any named branch has more than one open head.
+    /// With `unnamed_heads = "fail"` closed extra heads are reported as well.
+    fn verify_heads(&self) -> Result<(), ErrorKind> {
+        let fail = self.config.unnamed_heads == UnnamedHeads::Fail;
+        if self.config.allow_unnamed_heads && !fail {
+            return Ok(());
+        }
+
+        info!("Looking for unnamed heads");
+        let mut report = String::new();
+        for (branch, head) in self.unnamed_heads(self.changelog.len(), fail)? {
+            report.push_str(&self.describe_head(&branch, head.revision)?);
+        }
+
+        if report.is_empty() {
+            Ok(())
+        } else {
+            Err(ErrorKind::UnnamedHeads(report))
+        }
+    }
+
+    /// Heads within `0..to` revisions, which are referenced neither by branch nor by bookmark
+    /// and are not reachable from their children on other branches.
+    fn unnamed_heads(
+        &self,
+        to: usize,
+        include_closed: bool,
+    ) -> Result<Vec<(String, BranchHead)>, ErrorKind> {
+        let bookmarked: HashSet<usize> = if self.exports_bookmarks() {
+            self.bookmarks()?.into_iter().map(|(_, x)| x).collect()
+        } else {
+            HashSet::new()
+        };
+        let bookmarks_only = self.config.bookmarks_only;
+        let mut result = vec![];
+        for (branch, mut heads) in self.branch_heads(to)? {
+            heads.retain(|x| include_closed || !x.closed);
+            if !bookmarks_only || branch == HG_DEFAULT_BRANCH {
+                heads.pop();
+            }
+            result.extend(
+                heads
+                    .into_iter()
+                    .filter(|x| !bookmarked.contains(&x.revision))
+                    .filter(|x| !x.merged)
+                    .map(|x| (branch.clone(), x)),
+            );
+        }
+        Ok(result)
+    }
+
+    /// Heads of every named branch within `0..to` revisions, sorted by revision.
+    fn branch_heads(&self, to: usize) -> Result<BTreeMap<String, Vec<BranchHead>>, ErrorKind> {
+        let mut branches = Vec::with_capacity(to);
+        let mut has_child = vec![false; to];
+        let mut merged = vec![false; to];
+        for header in self
+            .inner
+            .range_header_iter(Revision::from(0).range_to(Revision::from(to as u32)))
+        {
+            let branch = header.extra.get(b"branch".as_slice()).map_or_else(
+                || Ok(HG_DEFAULT_BRANCH.into()),
+                |x| {
+                    self.decoder
+                        .decode(x)
+                        .map(|(branch, _)| branch.into_owned())
+                },
+            )?;
+            let closed = header.extra.get(b"close".as_slice()).map(Vec::as_slice) == Some(b"1");
+            for parent in header.p1.iter().chain(header.p2.iter()) {
+                let parent = usize::from(*parent);
+                merged[parent] = true;
+                if branches
+                    .get(parent)
+                    .is_some_and(|(x, _): &(String, bool)| x == &branch)
+                {
+                    has_child[parent] = true;
+                }
+            }
+            branches.push((branch, closed));
+        }
+
+        let mut heads: BTreeMap<String, Vec<BranchHead>> = BTreeMap::new();
+        for (revision, (branch, closed)) in branches.into_iter().enumerate() {
+            if !has_child[revision] {
+                heads.entry(branch).or_default().push(BranchHead {
+                    revision,
+                    closed,
+                    merged: merged[revision],
+                });
+            }
+        }
+        Ok(heads)
+    }
+
+    fn describe_head(&self, branch: &str, revision: usize) -> Result<String, ErrorKind> {
+        let node = self
+            .changelog
+            .node(revision)
+            .map(|x| x.short())
+            .unwrap_or_default();
+        let header = self
+            .inner
+            .range_header_iter(
+                Revision::from(revision as u32).range_to((revision as u32 + 1).into()),
+            )
+            .next()
+            .ok_or_else(|| ErrorKind::WrongChangelog(format!("missing revision {}", revision)))?;
+        let desc = to_str(&header.comment);
+        Ok(format!(
+            "  branch '{}': {}:{} | {} | {} | {}\n",
+            branch,
+            revision,
+            node,
+            to_str(&header.user),
+            header.time,
+            desc.lines().next().unwrap_or_default()
+        ))
+    }
+
+    /// Mercurial metadata files listed in `strip_files` are not exported to Git.
+    fn is_stripped(&self, path: &[u8]) -> bool {
+        self.config
+            .strip_files
+            .iter()
+            .any(|stripped| stripped.as_bytes() == path)
+    }
+
+    /// Paths not matching `include_paths` or matching `exclude_paths` are not exported,
+    /// largefiles standins are matched by original path.
+    fn is_filtered(&self, path: &[u8]) -> bool {
+        let path = if self.config.largefiles_to_lfs {
+            largefiles::original_path(path).unwrap_or(path)
+        } else {
+            path
+        };
+        if let Some(dir) = &self.config.split_subdirectory {
+            if filter::subdirectory(dir, path).is_none() {
+                return true;
+            }
+        }
+        !self.path_filter.is_match(&to_str(path))
+    }
+
+    /// Whether change of file is not exported as is.
+    fn is_skipped(&self, path: &[u8]) -> bool {
+        self.is_filtered(path) || self.is_stripped(path) && !self.is_converted(path)
+    }
+
+    /// Git path of exported file: relative to `split_subdirectory` and renamed by `path_renames`.
+    fn git_path<'p>(&self, path: &'p [u8]) -> Cow<'p, [u8]> {
+        let path = match &self.config.split_subdirectory {
+            Some(dir) => filter::subdirectory(dir, path).unwrap_or(path),
+            None => path,
+        };
+        filter::rename(&self.config.path_renames, path)
+    }
+
+    /// Git path of changed file by `path_renames`, `None` if deletion is not written
+    /// because other file of revision is renamed to its path. Modified paths are
+    /// collected in state, two modified files renamed to one path is an error.
+    fn renamed_path<'p>(
+        &self,
+        path: &'p [u8],
+        deleted: bool,
+        revision: Revision,
+        state: &mut ExportState,
+    ) -> Result<Option<Cow<'p, [u8]>>, ErrorKind> {
+        let target = self.git_path(path);
+        if self.config.path_renames.is_empty() {
+            return Ok(Some(target));
+        }
+        if deleted {
+            return Ok(state.renamed.is_deleted(path, &target).then_some(target));
+        }
+        match state.renamed.modify(path, &target) {
+            Ok(()) => Ok(Some(target)),
+            Err(source) => Err(ErrorKind::PathRenameCollision(
+                revision.0 as usize,
+                to_string(&source),
+                to_string(path),
+                to_string(&target),
+            )),
+        }
+    }
+
+    /// Files exported in converted form: `.hgignore` and subrepository state.
+    fn is_converted(&self, path: &[u8]) -> bool {
+        match path {
+            b".hgignore" => self.config.hgignore != HgIgnore::Keep,
+            b".hgsub" | b".hgsubstate" => self.config.subrepos.is_some(),
+            _ => false,
+        }
+    }
+
+    fn drops_empty_commits(&self) -> bool {
+        self.config.drop_empty_commits || self.config.split_subdirectory.is_some()
+    }
+
+    /// Nearest exported ancestor of revision or revision itself, `None` if there is none.
+    /// Revisions are checked from the first one on demand, so earlier imports are replayed.
+    fn survivor(&self, revision: usize, state: &mut ExportState) -> Option<usize> {
+        if !self.drops_empty_commits() {
+            return Some(revision);
+        }
+        let from = state.survivors.len();
+        if from <= revision {
+            for (rev, header) in (from..).zip(self.inner.range_header_iter(from..revision + 1)) {
+                let parents = exported_parents(
+                    (header.p1, header.p2),
+                    &state.survivors,
+                    &state.exported_parents,
+                );
+                let survivor = self.header_survivor(rev, &header, parents);
+                state.survivors.push(survivor);
+                state.exported_parents.push(parents);
+            }
+        }
+        state.survivors[revision]
+    }
+
+    /// Changeset is dropped if all its changes are in stripped or filtered files
+    /// and it has at most one exported parent, which becomes its survivor.
+    /// Only changes against first parent are known, so merge is never dropped
+    /// in favour of second parent. Changesets closing a branch are exported,
+    /// as well as changesets without changes unless `split_subdirectory` is set.
+    fn header_survivor(
+        &self,
+        revision: usize,
+        header: &ChangesetHeader,
+        parents: (Option<usize>, Option<usize>),
+    ) -> Option<usize> {
+        let closed = header
+            .extra
+            .get(b"close".as_slice())
+            .is_some_and(|x| x == b"1");
+        let unchanged = header.files.iter().all(|path| self.is_skipped(path))
+            && (!header.files.is_empty() || self.config.split_subdirectory.is_some());
+        match parents {
+            (p1, None) if unchanged && !closed => p1,
+            _ => Some(revision),
+        }
+    }
+
+    fn exports_bookmarks(&self) -> bool {
+        self.config.export_bookmarks || self.config.bookmarks_only
+    }
+
+    /// Bookmarks from `.hg/bookmarks` with revisions they point to.
+    fn bookmarks(&self) -> Result<Vec<(String, usize)>, ErrorKind> {
+        let path = self.path.join(".hg").join("bookmarks");
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        let mut bookmarks = vec![];
+        for line in read_file(path)?.lines() {
+            let Some((node, name)) = line.split_once(' ') else {
+                continue;
+            };
+            match NodeId::from_hex(node).and_then(|x| self.changelog.revision(&x)) {
+                Some(revision) => bookmarks.push((name.to_string(), revision)),
+                None => warn!("Bookmark {} points to unknown node {}", name, node),
+            }
+        }
+        Ok(bookmarks)
+    }
+
+    /// Tags from `.hg/cache/tags2-visible` or `.hgtags` with revisions and nodes they point to.
+    fn tags(&self) -> Result<BTreeMap<String, (usize, NodeId)>, ErrorKind> {
+        let mut path = self.path.join(".hg").join("cache").join("tags2-visible");
+        if !path.exists() {
+            path = self.path.join(".hgtags");
+        }
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let mut tags = BTreeMap::new();
+        for line in read_file(path)?.lines() {
+            let Some((node, name)) = line.split_once(' ') else {
+                continue;
+            };
+            let Some(node) = NodeId::from_hex(node) else {
+                continue;
+            };
+            if let Some(revision) = self.changelog.revision(&node) {
+                tags.insert(name.to_string(), (revision, node));
+            } else {
+                tags.remove(name);
+            }
+        }
+        Ok(tags)
     }
 
     fn changelog_len(&self) -> Result<usize, ErrorKind> {
@@ -235,65 +638,176 @@ impl<'a> MercurialRepo<'a> {
         self.inner.par_range_iter(range.into())
     }
 
+    /// Git branch name for Mercurial branch, taken from `brmap` or sanitized.
+    fn git_branch<'b>(
+        &self,
+        branch: &str,
+        brmap: &'b mut HashMap<String, String>,
+        default_branch: &str,
+    ) -> &'b String {
+        brmap.entry(branch.into()).or_insert_with(|| {
+            sanitize_branchname(
+                branch,
+                if branch != default_branch || self.config.prefix_default_branch {
+                    self.config.branch_prefix.as_ref()
+                } else {
+                    None
+                },
+                self.env.fix_wrong_branchname,
+            )
+        })
+    }
+
     fn export_commit(
         &self,
         changeset: &mut Changeset,
         count: usize,
-        brmap: &mut HashMap<String, String>,
+        state: &mut ExportState<'a>,
         output: &mut dyn Write,
         default_branch: &str,
     ) -> Result<usize, ErrorKind> {
         let header = &changeset.header;
+        let revision = changeset.revision;
+
+        let mut transcoded = vec![];
+        let (user, user_encoding) = self.decoder.decode(&header.user)?;
+        transcoded.extend(user_encoding.map(|encoding| ("user", encoding)));
+        let user = self.fixup_user(&user)?;
+
+        let closed = header.extra.get(b"close".as_slice()).map(Vec::as_slice) == Some(b"1");
+        let (hg_branch, branch_encoding) = self.decoder.decode(changeset_branch(&header.extra))?;
+        transcoded.extend(branch_encoding.map(|encoding| ("branch", encoding)));
+        let hg_branch = hg_branch.as_ref();
+        let branch = hg_branch_key(hg_branch, default_branch);
+        let (branch, reference) = if self.config.bookmarks_only && branch != default_branch {
+            (branch.to_string(), BOOKMARKS_ONLY_REF.to_string())
+        } else {
+            let branch = self.git_branch(branch, &mut state.brmap, default_branch);
+            (branch.clone(), format!("refs/heads/{}", branch))
+        };
+
+        let mark = self.mark(revision);
 
-        let user = self.fixup_user(std::str::from_utf8(&header.user)?)?;
+        let (desc, desc_encoding) = if self.decoder.is_empty() {
+            (String::from_utf8_lossy(&header.comment), None)
+        } else {
+            self.decoder.decode(&header.comment)?
+        };
+        transcoded.extend(desc_encoding.map(|encoding| ("message", encoding)));
+        if !transcoded.is_empty() {
+            self.report_transcoded(revision, &transcoded, state)?;
+        }
+
+        let desc = if self.config.rewrite_hg_hashes {
+            self.rewrite_hg_hashes(&desc, revision.into(), state, output)?
+        } else {
+            desc
+        };
+        let desc = self.message_rewrites.apply(&desc);
+
+        let time = header.time.timestamp_secs();
+        let tz = git_timezone(header.time.tz_offset_secs());
 
-        let mut branch = None;
-        let mut closed = false;
-        for (key, value) in &header.extra {
-            if key == b"branch" {
-                branch = Some(value.as_slice());
+        if self.config.annotated_tags {
+            for file in &changeset.files {
+                if let (b".hgtags", Some(data)) = (file.path.as_slice(), &file.data) {
+                    state.add_tag_origins(
+                        file_content(data),
+                        &format!("{} {} {}", user, time, tz),
+                        &desc,
+                    );
+                }
             }
+        }
+
+        let subrepos = if self.config.subrepos.is_some() {
+            self.update_subrepos(changeset, state)?
+        } else {
+            None
+        };
 
-            if key == b"close" && value == b"1" {
-                closed = true;
+        match self.survivor(revision.into(), state) {
+            Some(survivor) if survivor == usize::from(revision) => (),
+            Some(survivor) => {
+                // Only stripped or filtered files changed: point branch and mark at the survivor.
+                let parent = self.mark(survivor);
+                info!(
+                    "{} ({}) | {} | dropped, only stripped or filtered files changed",
+                    mark, revision.0, branch
+                );
+                writeln!(output, "reset {}", reference)?;
+                writeln!(output, "from :{}\n", parent)?;
+                writeln!(output, "alias")?;
+                writeln!(output, "mark :{}", mark)?;
+                writeln!(output, "to :{}\n", parent)?;
+                return Ok(count + 1);
+            }
+            None => {
+                info!(
+                    "{} ({}) | {} | dropped, no exported ancestor",
+                    mark, revision.0, branch
+                );
+                return Ok(count + 1);
             }
         }
-        let branch: String =
-            std::str::from_utf8(branch.unwrap_or(default_branch.as_bytes()))?.into();
+        let (p1, p2) = exported_parents(
+            (header.p1, header.p2),
+            &state.survivors,
+            &state.exported_parents,
+        );
 
-        let branch = brmap.entry(branch.clone()).or_insert_with(|| {
-            sanitize_branchname(
-                &branch,
-                if branch != default_branch || self.config.prefix_default_branch {
-                    self.config.branch_prefix.as_ref()
-                } else {
-                    None
-                },
-                self.env.fix_wrong_branchname,
-            )
-        });
+        let desc = if self.config.provenance {
+            match self.provenance(header, revision.into(), state, output)? {
+                Some(provenance) => Cow::Owned(message::append_trailers(&desc, &provenance)),
+                None => desc,
+            }
+        } else {
+            desc
+        };
 
-        let revision = changeset.revision;
+        let desc = match &self.trailers {
+            Some(trailers) => {
+                let node = self.node(revision)?;
+                Cow::Owned(trailers.append(
+                    &desc,
+                    &message::TrailerValues {
+                        node: &node.to_string(),
+                        revision: revision.0 as usize,
+                        branch: hg_branch,
+                    },
+                ))
+            }
+            None => desc,
+        };
 
-        if header.p1.is_some() || header.p2.is_some() || revision != 0.into() {
-            writeln!(output, "reset refs/heads/{}", branch)?;
+        if p1.is_some() || p2.is_some() || revision != 0.into() {
+            writeln!(output, "reset {}", reference)?;
         }
-        let desc = String::from_utf8_lossy(&header.comment);
-
-        let time = header.time.timestamp_secs();
-        let timezone = header.time.tz_offset_secs();
-        let tz = format!("{:+03}{:02}", -timezone / 3600, ((-timezone % 3600) / 60));
 
-        writeln!(output, "commit refs/heads/{}", branch)?;
-        let mark = self.mark(revision);
+        writeln!(output, "commit {}", reference)?;
         writeln!(output, "mark :{}", mark)?;
 
+        let committer = match self.hg_git_committer(header)? {
+            Some(committer) => committer,
+            None => format!("{} {} {}", user, time, tz),
+        };
+
         writeln!(output, "author {} {} {}", user, time, tz)?;
-        writeln!(output, "committer {} {} {}", user, time, tz)?;
-        writeln!(output, "data {}", desc.len() + 1)?;
-        writeln!(output, "{}\n", desc)?;
+        writeln!(output, "committer {}", committer)?;
+        let encoded = desc_encoding
+            .filter(|_| self.config.keep_message_encoding)
+            .and_then(|encoding| Some((encoding, encoding.encode(&desc)?)));
+        if let Some((encoding, desc)) = encoded {
+            writeln!(output, "encoding {}", encoding.name())?;
+            writeln!(output, "data {}", desc.len() + 1)?;
+            output.write_all(&desc)?;
+            writeln!(output, "\n")?;
+        } else {
+            writeln!(output, "data {}", desc.len() + 1)?;
+            writeln!(output, "{}\n", desc)?;
+        }
 
-        match (header.p1, header.p2) {
+        match (p1, p2) {
             (Some(p1), Some(p2)) => {
                 writeln!(output, "from :{}", self.mark(p1))?;
                 writeln!(output, "merge :{}", self.mark(p2))?;
@@ -317,29 +831,98 @@ impl<'a> MercurialRepo<'a> {
         }
 
         let prefix = strip_leading_slash(self.config.path_prefix.as_ref(), "");
+        // Root commits get `.gitattributes` for `lfs_patterns`, later ones inherit it.
+        let mut lfs_changed = !self.lfs_patterns.is_empty() && p1.is_none() && p2.is_none();
+        // Changed `.gitattributes` of Mercurial is written merged with Git LFS rules.
+        if self.lfs_enabled() {
+            if let Some(gitattributes) = self.changed_gitattributes(changeset) {
+                state
+                    .gitattributes
+                    .insert(revision.0 as usize, Rc::new(gitattributes));
+                lfs_changed = true;
+            }
+        }
+        state.renamed = filter::RenamedPaths::default();
         for file in &mut changeset.files {
+            if self.is_filtered(&file.path)
+                || self.lfs_enabled() && self.is_gitattributes(&file.path)
+            {
+                continue;
+            }
+            if file.path == b".hgsub" || file.path == b".hgsubstate" {
+                if self.is_converted(&file.path) {
+                    continue;
+                }
+            } else if file.path == b".hgignore" && self.is_converted(&file.path) {
+                self.export_gitignore(file.data.as_deref(), &prefix, revision, output)?;
+                if self.config.hgignore == HgIgnore::Replace {
+                    continue;
+                }
+            }
+            let largefile = if self.config.largefiles_to_lfs {
+                largefiles::original_path(&file.path)
+            } else {
+                None
+            };
+            if largefile.is_none() && self.is_stripped(&file.path) {
+                continue;
+            }
+            let deleted = file.data.is_none();
+            let Some(path) =
+                self.renamed_path(largefile.unwrap_or(&file.path), deleted, revision, state)?
+            else {
+                continue;
+            };
+            if largefile.is_some() {
+                self.export_largefile(&path, file, &prefix, revision, state, output)?;
+                lfs_changed = true;
+                continue;
+            }
             match (&mut file.data, &mut file.manifest_entry) {
                 (None, None) => {
                     write!(output, "D {}", prefix)?;
-                    output.write_all(&file.path)?;
+                    output.write_all(&path)?;
                     writeln!(output)?;
                 }
                 (Some(data), Some(manifest_entry)) => {
+                    let redacted = self.redact(&file.path, file_content(data), revision, state);
+                    let data = match &redacted {
+                        None => file_content(data),
+                        Some(redact::Redacted {
+                            data: Some(data), ..
+                        }) => data,
+                        Some(redact::Redacted { data: None, .. }) => {
+                            write!(output, "D {}", prefix)?;
+                            output.write_all(&path)?;
+                            writeln!(output)?;
+                            continue;
+                        }
+                    };
                     write!(
                         output,
                         "M {} inline {}",
-                        match manifest_entry.details {
-                            ManifestEntryDetails::File(FileType::Symlink) => "120000",
-                            ManifestEntryDetails::File(FileType::Executable) => "100755",
-                            ManifestEntryDetails::Tree
-                            | ManifestEntryDetails::File(FileType::Regular) => "100644",
-                        },
+                        file_mode(&manifest_entry.details),
                         prefix
                     )?;
-                    output.write_all(&file.path)?;
-                    let data = file_content(data);
-                    writeln!(output, "\ndata {}", data.len())?;
-                    output.write_all(data)?;
+                    output.write_all(&path)?;
+                    let symlink = matches!(
+                        manifest_entry.details,
+                        ManifestEntryDetails::File(FileType::Symlink)
+                    );
+                    let lfs = if symlink {
+                        None
+                    } else {
+                        self.lfs_tracks(&path, data.len(), revision, state)
+                    };
+                    if let Some(by_path) = lfs {
+                        let pointer = lfs_pointer(data, state)?;
+                        writeln!(output, "\ndata {}", pointer.len())?;
+                        output.write_all(&pointer)?;
+                        lfs_changed |= by_path;
+                    } else {
+                        writeln!(output, "\ndata {}", data.len())?;
+                        output.write_all(data)?;
+                    }
                 }
                 _ => {
                     return Err(ErrorKind::WrongFileData(
@@ -349,7 +932,23 @@ impl<'a> MercurialRepo<'a> {
             }
         }
 
-        if closed {
+        if lfs_changed {
+            self.export_lfs_attributes(&prefix, revision, state, output)?;
+        }
+
+        if let Some((parent, subrepos)) = subrepos {
+            self.export_submodules(&parent, &subrepos, &prefix, revision, state, output)?;
+        }
+
+        if self.config.notes.is_some() {
+            self.export_note(changeset, hg_branch, &committer, state, output)?;
+        }
+
+        if !self.git_commits.is_empty() && state.responses.is_some() {
+            self.check_git_commit(revision, state, output)?;
+        }
+
+        if closed && !self.config.bookmarks_only {
             writeln!(output, "reset refs/tags/archive/{}", branch)?;
             writeln!(output, "from :{}\n", self.mark(revision))?;
 
@@ -359,26 +958,1248 @@ impl<'a> MercurialRepo<'a> {
         Ok(count + 1)
     }
 
+    /// Writes note with changeset node, revision, branch and selected extras
+    /// for exported commit to notes ref.
+    fn export_note(
+        &self,
+        changeset: &Changeset,
+        hg_branch: &str,
+        committer: &str,
+        state: &mut ExportState,
+        output: &mut dyn Write,
+    ) -> Result<(), ErrorKind> {
+        let Some(notes) = &self.config.notes else {
+            return Ok(());
+        };
+        let revision = changeset.revision;
+        let node = self.node(revision)?;
+        let mut note = format!(
+            "node: {}\nrevision: {}\nbranch: {}\n",
+            node, revision.0, hg_branch
+        );
+        for key in &notes.extras {
+            if let Some(value) = changeset.header.extra.get(key.as_bytes()) {
+                note.push_str(&format!("{}: {}\n", key, to_str(value)));
+            }
+        }
+
+        writeln!(output, "commit {}", notes.reference)?;
+        writeln!(output, "committer {}", committer)?;
+        let message = format!("Mercurial metadata of revision {}", revision.0);
+        writeln!(output, "data {}", message.len() + 1)?;
+        writeln!(output, "{}", message)?;
+        // Existing notes are continued by the first note of import, later ones follow it.
+        if state.notes_exist {
+            writeln!(output, "from {}^0", notes.reference)?;
+            state.notes_exist = false;
+        }
+        writeln!(output, "N inline :{}", self.mark(revision))?;
+        writeln!(output, "data {}", note.len())?;
+        writeln!(output, "{}", note)?;
+        Ok(())
+    }
+
+    fn node(&self, revision: Revision) -> Result<changelog::NodeId, ErrorKind> {
+        self.changelog
+            .node(revision.0 as usize)
+            .ok_or_else(|| ErrorKind::WrongChangelog(format!("missing revision {}", revision.0)))
+    }
+
+    /// Writes `.gitignore` converted from `.hgignore` content, `None` means deleted file.
+    fn export_gitignore(
+        &self,
+        hgignore: Option<&[u8]>,
+        prefix: &str,
+        revision: Revision,
+        output: &mut dyn Write,
+    ) -> Result<(), ErrorKind> {
+        let hgignore = match hgignore {
+            Some(hgignore) => hgignore::convert(file_content(hgignore)),
+            None => {
+                writeln!(output, "D {}.gitignore", prefix)?;
+                return Ok(());
+            }
+        };
+        for pattern in &hgignore.untranslated {
+            warn!(
+                "Revision {}: .hgignore pattern '{}' is not translated to .gitignore",
+                revision.0, pattern
+            );
+        }
+        writeln!(output, "M 100644 inline {}.gitignore", prefix)?;
+        writeln!(output, "data {}", hgignore.content.len())?;
+        output.write_all(&hgignore.content)?;
+        Ok(())
+    }
+
+    /// Writes Git LFS pointer for largefile standin and stores largefile as LFS object.
+    fn export_largefile(
+        &self,
+        path: &[u8],
+        standin: &ChangesetFile,
+        prefix: &str,
+        revision: Revision,
+        state: &mut ExportState,
+        output: &mut dyn Write,
+    ) -> Result<(), ErrorKind> {
+        self.lfs_paths(revision, state).insert(to_string(path));
+        let (Some(data), Some(manifest_entry)) = (&standin.data, &standin.manifest_entry) else {
+            write!(output, "D {}", prefix)?;
+            output.write_all(path)?;
+            writeln!(output)?;
+            return Ok(());
+        };
+
+        let hash = largefiles::standin_hash(file_content(data));
+        let usercache = self
+            .config
+            .largefiles_usercache
+            .clone()
+            .or_else(largefiles::default_usercache);
+        let content = match largefiles::read(&self.path, usercache.as_deref(), &hash)? {
+            Some(largefile) => {
+                let source = largefiles::original_path(&standin.path).unwrap_or(path);
+                match self.redact(source, &largefile, revision, state) {
+                    None => lfs_pointer(&largefile, state)?,
+                    Some(redact::Redacted {
+                        data: Some(data), ..
+                    }) => lfs_pointer(&data, state)?,
+                    Some(redact::Redacted { data: None, .. }) => {
+                        write!(output, "D {}", prefix)?;
+                        output.write_all(path)?;
+                        writeln!(output)?;
+                        return Ok(());
+                    }
+                }
+            }
+            None => match self.config.missing_largefiles {
+                MissingLargefiles::Fail => {
+                    return Err(ErrorKind::MissingLargefile(
+                        revision.0 as usize,
+                        to_string(path),
+                        hash,
+                    ))
+                }
+                MissingLargefiles::Placeholder => {
+                    warn!(
+                        "Revision {}: largefile '{}' ({}) is missing, placeholder is written",
+                        revision.0,
+                        to_str(path),
+                        hash
+                    );
+                    format!("largefile {} is missing\n", hash).into_bytes()
+                }
+            },
+        };
+
+        write!(
+            output,
+            "M {} inline {}",
+            file_mode(&manifest_entry.details),
+            prefix
+        )?;
+        output.write_all(path)?;
+        writeln!(output, "\ndata {}", content.len())?;
+        output.write_all(&content)?;
+        Ok(())
+    }
+
+    /// Replaces prefixes of earlier changeset nodes in message by their Git commits,
+    /// abbreviated to the same length but at least 12 characters.
+    fn rewrite_hg_hashes<'m>(
+        &self,
+        message: &'m str,
+        revision: usize,
+        state: &mut ExportState,
+        output: &mut dyn Write,
+    ) -> Result<Cow<'m, str>, ErrorKind> {
+        lazy_static! {
+            static ref HG_HASH: Regex = Regex::new(r"\b[0-9a-f]{8,40}\b").unwrap();
+        }
+        let mut result = String::new();
+        let mut last = 0;
+        for hash in HG_HASH.find_iter(message) {
+            let Some(referenced) = self.changelog.revision_by_prefix(hash.as_str()) else {
+                continue;
+            };
+            if referenced >= revision {
+                continue;
+            }
+            let Some(commit) = self.commit_id(referenced, state, output)? else {
+                continue;
+            };
+            result.push_str(&message[last..hash.start()]);
+            result.push_str(&commit[..hash.len().max(12)]);
+            last = hash.end();
+        }
+        if last == 0 {
+            return Ok(Cow::Borrowed(message));
+        }
+        result.push_str(&message[last..]);
+        Ok(Cow::Owned(result))
+    }
+
+    /// Committer line from `committer` extra of changeset converted by hg-git,
+    /// `None` if `hg_git` is disabled or extra is missing.
+    fn hg_git_committer(&self, header: &ChangesetHeader) -> Result<Option<String>, ErrorKind> {
+        if !self.config.hg_git {
+            return Ok(None);
+        }
+        let Some(committer) = header.extra.get(b"committer".as_slice()) else {
+            return Ok(None);
+        };
+        let (committer, _) = self.decoder.decode(committer)?;
+        // hg-git writes "user time offset", old versions only user.
+        let mut parts = committer.rsplitn(3, ' ');
+        if let (Some(offset), Some(time), Some(user)) = (parts.next(), parts.next(), parts.next()) {
+            if let (Ok(offset), Ok(time)) = (offset.parse(), time.parse::<i64>()) {
+                let user = self.fixup_user(user)?;
+                return Ok(Some(format!("{} {} {}", user, time, git_timezone(offset))));
+            }
+        }
+        let user = self.fixup_user(&committer)?;
+        let time = header.time.timestamp_secs();
+        let tz = git_timezone(header.time.tz_offset_secs());
+        Ok(Some(format!("{} {} {}", user, time, tz)))
+    }
+
+    /// Adds changeset with metadata transcoded to UTF-8 to encoding report.
+    fn report_transcoded(
+        &self,
+        revision: Revision,
+        transcoded: &[(&str, encoding::Encoding)],
+        state: &mut ExportState,
+    ) -> Result<(), ErrorKind> {
+        let node = self.node(revision)?;
+        let fields = transcoded
+            .iter()
+            .map(|(field, encoding)| format!("{} {}", field, encoding.name()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        state
+            .encoding_report
+            .push(format!("{}\t{}\t{}", revision.0, node, fields));
+        Ok(())
+    }
+
+    /// Compares exported commit with Git commit hg-git converted changeset from,
+    /// mismatch is added to report.
+    fn check_git_commit(
+        &self,
+        revision: Revision,
+        state: &mut ExportState,
+        output: &mut dyn Write,
+    ) -> Result<(), ErrorKind> {
+        let node = self.node(revision)?;
+        let Some(expected) = self.git_commits.get(&node) else {
+            return Ok(());
+        };
+        if let Some(commit) = self.commit_id(revision.into(), state, output)? {
+            if &commit != expected {
+                state.commit_mismatches.push(format!(
+                    "{}\t{}\t{}\t{}",
+                    revision.0, node, expected, commit
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Provenance lines for changeset extras written by rebase, graft, transplant,
+    /// amend and convert. Grafted or transplanted changeset which is exported itself
+    /// gives `(cherry picked from commit ..)` line, other extras give trailers.
+    fn provenance(
+        &self,
+        header: &ChangesetHeader,
+        revision: usize,
+        state: &mut ExportState,
+        output: &mut dyn Write,
+    ) -> Result<Option<String>, ErrorKind> {
+        let mut cherry_picked = vec![];
+        let mut trailers = vec![];
+        for (key, trailer) in PROVENANCE_EXTRAS {
+            let Some(value) = header.extra.get(key.as_bytes()) else {
+                continue;
+            };
+            if key == "convert_revision" {
+                trailers.push(format!("{}: {}", trailer, to_str(value)));
+                continue;
+            }
+            let node = NodeId::from_bytes(value).or_else(|| NodeId::from_hex(&to_str(value)));
+            let Some(node) = node else {
+                trailers.push(format!("{}: {}", trailer, to_str(value)));
+                continue;
+            };
+            if key == "source" || key == "transplant_source" {
+                if let Some(source) = self.changelog.revision(&node) {
+                    if source < revision && self.survivor(source, state) == Some(source) {
+                        if let Some(commit) = self.commit_id(source, state, output)? {
+                            cherry_picked.push(format!("(cherry picked from commit {})", commit));
+                            continue;
+                        }
+                    }
+                }
+            }
+            trailers.push(format!("{}: {}", trailer, node));
+        }
+        cherry_picked.append(&mut trailers);
+        if cherry_picked.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(cherry_picked.join("\n")))
+        }
+    }
+
+    /// Git commit of exported revision (or its survivor), asked from fast-import by mark.
+    /// `None` if revision has no commit or target does not tell commits.
+    fn commit_id(
+        &self,
+        revision: usize,
+        state: &mut ExportState,
+        output: &mut dyn Write,
+    ) -> Result<Option<String>, ErrorKind> {
+        let Some(survivor) = self.survivor(revision, state) else {
+            return Ok(None);
+        };
+        if let Some(commit) = state.commit_ids.get(&survivor) {
+            return Ok(Some(commit.clone()));
+        }
+        let Some(responses) = state.responses.as_mut() else {
+            warn!("Git commits are not known, Mercurial hashes are kept");
+            return Ok(None);
+        };
+        writeln!(output, "get-mark :{}", self.mark(survivor))?;
+        output.flush()?;
+        let mut commit = String::new();
+        responses.read_line(&mut commit)?;
+        let commit = commit.trim_end().to_string();
+        if commit.len() != 40 {
+            return Err(ErrorKind::WrongFastImportResponse(commit));
+        }
+        state.commit_ids.insert(survivor, commit.clone());
+        Ok(Some(commit))
+    }
+
+    /// Applies `redactions` to file content, changes are added to report.
+    fn redact(
+        &self,
+        path: &[u8],
+        data: &[u8],
+        revision: Revision,
+        state: &mut ExportState,
+    ) -> Option<redact::Redacted> {
+        if self.redactions.is_empty() {
+            return None;
+        }
+        let path = to_str(path);
+        let redacted = self.redactions.apply(&path, data)?;
+        let node = self
+            .changelog
+            .node(revision.0 as usize)
+            .map(|node| node.to_hex())
+            .unwrap_or_default();
+        let actions = redacted.actions.join(", ");
+        info!("Revision {}: {} {}", revision.0, path, actions);
+        state
+            .redaction_report
+            .push(format!("{}\t{}\t{}\t{}", revision.0, node, path, actions));
+        Some(redacted)
+    }
+
+    /// Paths tracked by Git LFS before revision. Without saved paths
+    /// they are restored from history, but only largefiles can be found there.
+    fn lfs_paths<'s>(
+        &self,
+        revision: Revision,
+        state: &'s mut ExportState,
+    ) -> &'s mut BTreeSet<String> {
+        state.lfs_paths.get_or_insert_with(|| {
+            self.inner
+                .range_header_iter(Revision::from(0).range_to(revision))
+                .flat_map(|header| header.files)
+                .filter_map(|path| {
+                    largefiles::original_path(&path).map(|path| to_string(&self.git_path(path)))
+                })
+                .collect()
+        })
+    }
+
+    /// Whether file is exported as Git LFS pointer by `lfs_patterns` or `lfs_size_threshold`.
+    /// Files matched by size only are tracked by path, `Some(true)` is returned for them
+    /// and for paths tracked earlier, e.g. by converted largefiles.
+    fn lfs_tracks(
+        &self,
+        path: &[u8],
+        size: usize,
+        revision: Revision,
+        state: &mut ExportState,
+    ) -> Option<bool> {
+        if self.lfs_patterns.is_empty()
+            && self.config.lfs_size_threshold.is_none()
+            && !self.config.largefiles_to_lfs
+        {
+            return None;
+        }
+        let path = to_string(path);
+        if self.lfs_patterns.is_match(&path) {
+            return Some(false);
+        }
+        let lfs_paths = self.lfs_paths(revision, state);
+        if lfs_paths.contains(&path) {
+            return Some(true);
+        }
+        if self
+            .config
+            .lfs_size_threshold
+            .is_some_and(|threshold| size > threshold)
+        {
+            lfs_paths.insert(path);
+            return Some(true);
+        }
+        None
+    }
+
+    /// Writes `.gitattributes` of Mercurial followed by lines which track `lfs_patterns`
+    /// and paths by Git LFS.
+    fn export_lfs_attributes(
+        &self,
+        prefix: &str,
+        revision: Revision,
+        state: &mut ExportState,
+        output: &mut dyn Write,
+    ) -> Result<(), ErrorKind> {
+        let mut attributes = self.gitattributes_at(revision.0 as usize, state)?.to_vec();
+        if !attributes.is_empty() && !attributes.ends_with(b"\n") {
+            attributes.push(b'\n');
+        }
+        attributes.extend_from_slice(self.lfs_patterns.attributes().as_bytes());
+        for path in state.lfs_paths.iter().flatten() {
+            attributes.extend_from_slice(lfs::attributes(&lfs::path_pattern(path)).as_bytes());
+        }
+        if attributes.is_empty() {
+            writeln!(output, "D {}.gitattributes", prefix)?;
+            return Ok(());
+        }
+        writeln!(output, "M 100644 inline {}.gitattributes", prefix)?;
+        writeln!(output, "data {}", attributes.len())?;
+        output.write_all(&attributes)?;
+        Ok(())
+    }
+
+    /// Whether `.gitattributes` is generated for Git LFS.
+    fn lfs_enabled(&self) -> bool {
+        self.config.largefiles_to_lfs
+            || !self.lfs_patterns.is_empty()
+            || self.config.lfs_size_threshold.is_some()
+    }
+
+    /// Whether file of Mercurial is exported as root `.gitattributes`.
+    fn is_gitattributes(&self, path: &[u8]) -> bool {
+        !self.is_filtered(path) && self.git_path(path).as_ref() == b".gitattributes"
+    }
+
+    /// Content of `.gitattributes` changed by changeset, empty if it is deleted.
+    fn changed_gitattributes(&self, changeset: &Changeset) -> Option<Vec<u8>> {
+        let file = changeset
+            .files
+            .iter()
+            .find(|file| self.is_gitattributes(&file.path))?;
+        let content = file.data.as_deref().map(file_content).unwrap_or_default();
+        Some(content.to_vec())
+    }
+
+    /// Mercurial `.gitattributes` of revision.
+    fn gitattributes_at(
+        &self,
+        revision: usize,
+        state: &mut ExportState,
+    ) -> Result<Rc<Vec<u8>>, ErrorKind> {
+        self.history_value(
+            ".gitattributes",
+            revision,
+            state,
+            |state| &mut state.gitattributes,
+            |path| self.is_gitattributes(path),
+            |changeset, _| self.changed_gitattributes(changeset),
+        )
+    }
+
+    /// Subrepositories of revision.
+    fn subrepos_at(
+        &self,
+        revision: usize,
+        state: &mut ExportState,
+    ) -> Result<Rc<Subrepos>, ErrorKind> {
+        self.history_value(
+            "subrepositories",
+            revision,
+            state,
+            |state| &mut state.subrepos,
+            |path| path == b".hgsub" || path == b".hgsubstate",
+            changed_subrepos,
+        )
+    }
+
+    /// Value of revision kept by files, e.g. subrepositories by `.hgsub`. Revisions
+    /// exported by earlier runs are replayed from the first one: `changed` gives value
+    /// of changeset which touched `is_tracked` files, others inherit value of first parent.
+    fn history_value<T: Default>(
+        &self,
+        name: &str,
+        revision: usize,
+        state: &mut ExportState,
+        values: for<'s> fn(&'s mut ExportState) -> &'s mut HashMap<usize, Rc<T>>,
+        is_tracked: impl Fn(&[u8]) -> bool,
+        changed: impl Fn(&Changeset, &T) -> Option<T>,
+    ) -> Result<Rc<T>, ErrorKind> {
+        if !values(state).contains_key(&revision) {
+            info!("Reading {} up to revision {}", name, revision);
+            let headers = self
+                .inner
+                .range_header_iter(Revision::from(0).range_to(Revision::from(revision as u32 + 1)));
+            for (current, header) in headers.enumerate() {
+                if values(state).contains_key(&current) {
+                    continue;
+                }
+                let parent = header
+                    .p1
+                    .and_then(|p1| values(state).get(&usize::from(p1)).cloned())
+                    .unwrap_or_default();
+                let value = if header.files.iter().any(|path| is_tracked(path)) {
+                    let changeset = self
+                        .inner
+                        .range_iter(current..current + 1)
+                        .next()
+                        .ok_or_else(|| {
+                            ErrorKind::WrongChangelog(format!("missing revision {}", current))
+                        })?;
+                    changed(&changeset, &parent).map(Rc::new)
+                } else {
+                    None
+                };
+                values(state).insert(current, value.unwrap_or(parent));
+            }
+        }
+        Ok(values(state)[&revision].clone())
+    }
+
+    /// Remembers subrepositories of changeset,
+    /// returns them with subrepositories of parent if `.hgsub` or `.hgsubstate` changed.
+    fn update_subrepos(
+        &self,
+        changeset: &Changeset,
+        state: &mut ExportState,
+    ) -> Result<Option<SubreposChange>, ErrorKind> {
+        let parent = match changeset.header.p1 {
+            Some(p1) => self.subrepos_at(p1.into(), state)?,
+            None => Rc::default(),
+        };
+        let revision = changeset.revision.into();
+        if let Some(subrepos) = changed_subrepos(changeset, &parent) {
+            let subrepos = Rc::new(subrepos);
+            state.subrepos.insert(revision, subrepos.clone());
+            Ok(Some((parent, subrepos)))
+        } else {
+            state.subrepos.insert(revision, parent);
+            Ok(None)
+        }
+    }
+
+    /// Writes `.gitmodules` and gitlinks of subrepositories changed since parent.
+    fn export_submodules(
+        &self,
+        parent: &Subrepos,
+        subrepos: &Subrepos,
+        prefix: &str,
+        revision: Revision,
+        state: &mut ExportState<'a>,
+        output: &mut dyn Write,
+    ) -> Result<(), ErrorKind> {
+        let modules = self.submodule_sources(subrepos);
+        if self.submodule_sources(parent) != modules {
+            // Git reads only root `.gitmodules`, paths in it include `path_prefix`.
+            if modules.is_empty() {
+                writeln!(output, "D .gitmodules")?;
+            } else {
+                let gitmodules = subrepo::gitmodules(&modules, |source| self.submodule_url(source));
+                writeln!(output, "M 100644 inline .gitmodules")?;
+                writeln!(output, "data {}", gitmodules.len())?;
+                output.write_all(&gitmodules)?;
+            }
+        }
+
+        for path in parent.revisions.keys() {
+            if !subrepos.revisions.contains_key(path) && !self.is_filtered(path.as_bytes()) {
+                if let Some(path) = self.renamed_path(path.as_bytes(), true, revision, state)? {
+                    write!(output, "D {}", prefix)?;
+                    output.write_all(&path)?;
+                    writeln!(output)?;
+                }
+            }
+        }
+        for (path, node) in &subrepos.revisions {
+            let source = subrepos.sources.get(path);
+            if parent.revisions.get(path) == Some(node) && parent.sources.get(path) == source {
+                continue;
+            }
+            if source.is_some_and(|source| self.inlines(source)) {
+                if !self.export_inline_subrepo(parent, subrepos, path, revision, state, output)? {
+                    warn!(
+                        "Revision {}: subrepository '{}' has no revision {}, it is not updated",
+                        revision.0, path, node
+                    );
+                }
+                continue;
+            }
+            if self.is_filtered(path.as_bytes()) {
+                continue;
+            }
+            match self.submodule_commit(source, node, state)? {
+                Some(commit) => {
+                    let Some(path) = self.renamed_path(path.as_bytes(), false, revision, state)?
+                    else {
+                        continue;
+                    };
+                    write!(output, "M 160000 {} {}", commit, prefix)?;
+                    output.write_all(&path)?;
+                    writeln!(output)?;
+                }
+                None => warn!(
+                    "Revision {}: no Git commit for subrepository '{}' at {}, gitlink is not updated",
+                    revision.0, path, node
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes changes of inlined subrepository tree since revision pinned by parent,
+    /// `false` if pinned revision is unknown.
+    fn export_inline_subrepo(
+        &self,
+        parent: &Subrepos,
+        subrepos: &Subrepos,
+        path: &str,
+        revision: Revision,
+        state: &mut ExportState<'a>,
+        output: &mut dyn Write,
+    ) -> Result<bool, ErrorKind> {
+        let prefix = strip_leading_slash(self.config.path_prefix.as_ref(), "");
+        let source = &subrepos.sources[path];
+        let Some(tree) = self.inline_subrepo_tree(source, &subrepos.revisions[path], state)? else {
+            return Ok(false);
+        };
+        let parent_tree = match (parent.sources.get(path), parent.revisions.get(path)) {
+            (Some(parent_source), Some(parent_node)) if parent_source == source => {
+                self.inline_subrepo_tree(source, parent_node, state)?
+            }
+            _ => None,
+        };
+        let parent_tree = parent_tree.unwrap_or_else(|| Rc::new(SubrepoTree::new()));
+        if parent_tree.is_empty()
+            && parent.revisions.contains_key(path)
+            && !self.is_filtered(path.as_bytes())
+        {
+            if let Some(path) = self.renamed_path(path.as_bytes(), true, revision, state)? {
+                write!(output, "D {}", prefix)?;
+                output.write_all(&path)?;
+                writeln!(output)?;
+            }
+        }
+
+        for file in parent_tree.keys() {
+            if !tree.contains_key(file) {
+                let file = [path.as_bytes(), b"/", file].concat();
+                if self.is_filtered(&file) {
+                    continue;
+                }
+                if let Some(file) = self.renamed_path(&file, true, revision, state)? {
+                    write!(output, "D {}", prefix)?;
+                    output.write_all(&file)?;
+                    writeln!(output)?;
+                }
+            }
+        }
+        for (file, entry) in tree.iter() {
+            if parent_tree.get(file) == Some(entry) {
+                continue;
+            }
+            let (mode, data) = entry;
+            let source = [path.as_bytes(), b"/", file].concat();
+            if self.is_filtered(&source) {
+                continue;
+            }
+            let Some(file) = self.renamed_path(&source, false, revision, state)? else {
+                continue;
+            };
+            let redacted = self.redact(&source, file_content(data), revision, state);
+            let data = match &redacted {
+                None => file_content(data),
+                Some(redact::Redacted {
+                    data: Some(data), ..
+                }) => data,
+                Some(redact::Redacted { data: None, .. }) => {
+                    write!(output, "D {}", prefix)?;
+                    output.write_all(&file)?;
+                    writeln!(output)?;
+                    continue;
+                }
+            };
+            write!(output, "M {} inline {}", mode, prefix)?;
+            output.write_all(&file)?;
+            writeln!(output, "\ndata {}", data.len())?;
+            output.write_all(data)?;
+        }
+        Ok(true)
+    }
+
+    /// Tree of inlined subrepository at pinned node, `None` if node is unknown.
+    fn inline_subrepo_tree(
+        &self,
+        source: &str,
+        node: &str,
+        state: &mut ExportState<'a>,
+    ) -> Result<Option<Rc<SubrepoTree>>, ErrorKind> {
+        if !state.inline_subrepos.contains_key(source) {
+            let Some(path_hg) = self
+                .subrepo_config(source)
+                .and_then(|config| config.path_hg.as_ref())
+            else {
+                return Ok(None);
+            };
+            info!("Opening subrepository {}", path_hg.to_string_lossy());
+            lazy_static! {
+                // Filters and rewrites of parent are applied to inlined paths.
+                static ref SUBREPO_CONFIG: config::RepositoryConfig = Default::default();
+            }
+            let repo = MercurialRepo::open(
+                path_hg,
+                &SUBREPO_CONFIG,
+                self.ignore_unknown_requirements,
+                self.env,
+            )?;
+            state
+                .inline_subrepos
+                .insert(source.into(), InlineSubrepo::new(repo));
+        }
+        state.inline_subrepos.get_mut(source).unwrap().tree(node)
+    }
+
+    fn subrepo_config(&self, source: &str) -> Option<&config::SubrepoConfig> {
+        self.config
+            .subrepos
+            .as_ref()
+            .and_then(|subrepos| subrepos.get(source))
+    }
+
+    /// Mercurial subrepository with `path_hg` is inlined in `inline_subrepos` mode.
+    fn inlines(&self, source: &str) -> bool {
+        self.config.inline_subrepos
+            && matches!(Source::parse(source), Source::Hg(_))
+            && self
+                .subrepo_config(source)
+                .is_some_and(|config| config.path_hg.is_some())
+    }
+
+    /// Subrepositories exported as submodules by Git path with `path_prefix`.
+    fn submodule_sources(&self, subrepos: &Subrepos) -> BTreeMap<String, String> {
+        let prefix = strip_leading_slash(self.config.path_prefix.as_ref(), "");
+        subrepos
+            .sources
+            .iter()
+            .filter(|(path, source)| !self.inlines(source) && !self.is_filtered(path.as_bytes()))
+            .map(|(path, source)| {
+                let path = to_string(&self.git_path(path.as_bytes()));
+                (format!("{}{}", prefix, path), source.clone())
+            })
+            .collect()
+    }
+
+    fn submodule_url(&self, source: &str) -> String {
+        self.subrepo_config(source)
+            .map_or_else(|| Source::parse(source).url().into(), |x| x.url.clone())
+    }
+
+    /// Git commit for pinned revision of subrepository. Git subrepository is pinned
+    /// to commit itself, Mercurial one is looked up in marks of its earlier import.
+    fn submodule_commit(
+        &self,
+        source: Option<&String>,
+        node: &str,
+        state: &mut ExportState,
+    ) -> Result<Option<String>, ErrorKind> {
+        let Some(source) = source else {
+            return Ok(None);
+        };
+        match Source::parse(source) {
+            Source::Git(_) => Ok(Some(node.into())),
+            Source::Svn(_) => Ok(None),
+            Source::Hg(_) => {
+                if !state.subrepo_commits.contains_key(source) {
+                    let commits = match self.subrepo_config(source) {
+                        Some(config) => SubrepoCommits::open(config)?,
+                        None => None,
+                    };
+                    state.subrepo_commits.insert(source.clone(), commits);
+                }
+                Ok(state.subrepo_commits[source]
+                    .as_ref()
+                    .and_then(|commits| commits.commit(node))
+                    .map(String::from))
+            }
+        }
+    }
+
+    /// Exports extra heads of named branches according to `unnamed_heads` policy,
+    /// otherwise they would not be referenced by anything in Git.
+    fn export_heads(
+        &self,
+        to: usize,
+        mut count: usize,
+        state: &mut ExportState,
+        output: &mut dyn Write,
+        default_branch: &str,
+    ) -> Result<usize, ErrorKind> {
+        info!("Exporting unnamed heads");
+        for (branch, head) in self.unnamed_heads(to, true)? {
+            let Some(revision) = self.survivor(head.revision, state) else {
+                continue;
+            };
+            let branch = self.git_branch(
+                hg_branch_key(&branch, default_branch),
+                &mut state.brmap,
+                default_branch,
+            );
+            let Some(reference) = self.unnamed_head_ref(branch, head.revision)? else {
+                return Err(ErrorKind::UnnamedHeads(
+                    self.describe_head(branch, head.revision)?,
+                ));
+            };
+            writeln!(output, "reset {}", reference)?;
+            writeln!(output, "from :{}", self.mark(revision))?;
+            writeln!(output)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Reference of unnamed head of Git `branch` by `unnamed_heads` policy,
+    /// `None` if the policy is to fail.
+    fn unnamed_head_ref(&self, branch: &str, revision: usize) -> Result<Option<String>, ErrorKind> {
+        let node = self
+            .changelog
+            .node(revision)
+            .ok_or_else(|| ErrorKind::WrongChangelog(format!("missing revision {}", revision)))?
+            .short();
+        Ok(match self.config.unnamed_heads {
+            UnnamedHeads::Branch => Some(format!("refs/heads/{}-head-{}", branch, node)),
+            UnnamedHeads::Namespace => Some(format!("refs/hg/heads/{}/{}", branch, node)),
+            UnnamedHeads::Tag => Some(format!(
+                "refs/tags/{}",
+                sanitize_name(
+                    &format!("{}-head-{}", branch, node),
+                    self.config.tag_prefix.as_ref(),
+                    "tag"
+                )
+            )),
+            UnnamedHeads::Fail => None,
+        })
+    }
+
+    /// Exports bookmarks as branches. Bookmark `@` is exported as default branch.
+    fn export_bookmarks(
+        &self,
+        to: usize,
+        mut count: usize,
+        state: &mut ExportState,
+        output: &mut dyn Write,
+        default_branch: &str,
+    ) -> Result<usize, ErrorKind> {
+        if !self.exports_bookmarks() {
+            return Ok(count);
+        }
+        info!("Exporting bookmarks");
+        let bookmarks_only = self.config.bookmarks_only;
+        let branches: HashSet<String> = if bookmarks_only {
+            [self
+                .git_branch(default_branch, &mut state.brmap, default_branch)
+                .clone()]
+            .into()
+        } else {
+            self.branch_heads(to)?
+                .into_keys()
+                .map(|branch| {
+                    self.git_branch(
+                        hg_branch_key(&branch, default_branch),
+                        &mut state.brmap,
+                        default_branch,
+                    )
+                    .clone()
+                })
+                .collect()
+        };
+        for (name, revision) in self.bookmarks()? {
+            if revision >= to {
+                continue;
+            }
+            let Some(revision) = self.survivor(revision, state) else {
+                continue;
+            };
+            let bookmark = if name == "@" {
+                self.git_branch(default_branch, &mut state.brmap, default_branch)
+                    .clone()
+            } else {
+                sanitize_branchname(
+                    &name,
+                    self.config.bookmark_prefix.as_ref(),
+                    self.env.fix_wrong_branchname,
+                )
+            };
+            if branches.contains(&bookmark) && !(bookmarks_only && name == "@") {
+                match self.config.bookmark_collision {
+                    BookmarkCollision::Branch => {
+                        warn!("Bookmark {} skipped, branch {} exists", name, bookmark);
+                        continue;
+                    }
+                    BookmarkCollision::Bookmark => {
+                        warn!("Branch {} moved to bookmark {}", bookmark, name);
+                    }
+                    BookmarkCollision::Fail => {
+                        return Err(ErrorKind::BookmarkCollision(name, bookmark));
+                    }
+                }
+            }
+            writeln!(output, "reset refs/heads/{}", bookmark)?;
+            writeln!(output, "from :{}", self.mark(revision))?;
+            writeln!(output)?;
+            count += 1;
+        }
+        if bookmarks_only {
+            writeln!(output, "reset {}", BOOKMARKS_ONLY_REF)?;
+            writeln!(output, "from 0000000000000000000000000000000000000000\n")?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Exports tags which are new or moved since `exported_tags`.
+    /// Without `exported_tags` (first run or state from older version)
+    /// tags on revisions before `range.start` are considered exported.
+    /// Tags with known origin in `state` are exported as annotated tags.
     fn export_tags(
         &self,
         range: Range<usize>,
+        exported_tags: Option<ExportedTags>,
         mut count: usize,
+        state: &mut ExportState,
         output: &mut dyn Write,
-    ) -> Result<usize, ErrorKind> {
+    ) -> Result<(usize, ExportedTags), ErrorKind> {
         info!("Exporting tags");
-        for (revision, tag) in self
-            .inner
-            .tags()?
-            .range(Revision::from(range.start as u32)..Revision::from(range.end as u32))
-        {
-            let tag = sanitize_name(&tag.name, self.config.tag_prefix.as_ref(), "tag");
+        let tags = self.tags()?;
+        let mut exported = exported_tags.map_or_else(
+            || {
+                tags.iter()
+                    .filter(|(_, (revision, _))| *revision < range.start)
+                    .map(|(name, (_, node))| (name.clone(), node.to_hex()))
+                    .collect()
+            },
+            |x| x.tags,
+        );
+
+        for (name, (revision, node)) in &tags {
+            let node_hex = node.to_hex();
+            if *revision >= range.end || exported.get(name) == Some(&node_hex) {
+                continue;
+            }
+            let Some(survivor) = self.survivor(*revision, state) else {
+                continue;
+            };
+            let tag = sanitize_name(name, self.config.tag_prefix.as_ref(), "tag");
 
-            writeln!(output, "reset refs/tags/{}", tag).unwrap();
-            writeln!(output, "from :{}", self.mark(*revision)).unwrap();
-            writeln!(output).unwrap();
+            if let Some(origin) = state.tag_origins.get(&(name.clone(), *node)) {
+                writeln!(output, "tag {}", tag)?;
+                writeln!(output, "from :{}", self.mark(survivor))?;
+                writeln!(output, "tagger {}", origin.tagger)?;
+                writeln!(output, "data {}", origin.message.len() + 1)?;
+                writeln!(output, "{}\n", origin.message)?;
+            } else {
+                writeln!(output, "reset refs/tags/{}", tag)?;
+                writeln!(output, "from :{}", self.mark(survivor))?;
+                writeln!(output)?;
+            }
+            exported.insert(name.clone(), node_hex);
             count += 1;
         }
-        Ok(count)
+
+        if self.config.delete_removed_tags {
+            let removed: Vec<_> = exported
+                .keys()
+                .filter(|x| !tags.contains_key(*x))
+                .cloned()
+                .collect();
+            for name in removed {
+                let tag = sanitize_name(&name, self.config.tag_prefix.as_ref(), "tag");
+                info!("Removing tag {}", tag);
+
+                writeln!(output, "reset refs/tags/{}", tag)?;
+                writeln!(output, "from 0000000000000000000000000000000000000000\n")?;
+                exported.remove(&name);
+                count += 1;
+            }
+        }
+        Ok((count, ExportedTags { tags: exported }))
+    }
+}
+
+/// Mutable state of single import run.
+struct ExportState<'a> {
+    brmap: HashMap<String, String>,
+    /// Tag name and node to changeset which added them to `.hgtags`.
+    tag_origins: HashMap<(String, NodeId), TagOrigin>,
+    /// Subrepositories of exported revisions.
+    subrepos: HashMap<usize, Rc<Subrepos>>,
+    /// Commits of Mercurial subrepositories by source.
+    subrepo_commits: HashMap<String, Option<SubrepoCommits>>,
+    /// Inlined Mercurial subrepositories by source.
+    inline_subrepos: HashMap<String, InlineSubrepo<'a>>,
+    /// Git LFS objects storage of target.
+    lfs_objects: Option<PathBuf>,
+    /// Paths tracked by Git LFS in `.gitattributes`, loaded on first use.
+    lfs_paths: Option<BTreeSet<String>>,
+    /// Nearest exported ancestor of each revision from the first one, see `survivor`.
+    survivors: Vec<Option<usize>>,
+    /// Exported parents of each revision from the first one, see `exported_parents`.
+    exported_parents: Vec<(Option<usize>, Option<usize>)>,
+    /// Lines of redaction report: revision, node, path and applied actions.
+    redaction_report: Vec<String>,
+    /// Responses of fast-import, `None` if target does not give them.
+    responses: Option<Responses>,
+    /// Git commits of exported revisions, asked from fast-import by marks.
+    commit_ids: HashMap<usize, String>,
+    /// Notes ref exists in target and is not continued by this import yet.
+    notes_exist: bool,
+    /// Lines of hg-git report: revision, node, original and exported Git commit.
+    commit_mismatches: Vec<String>,
+    /// Lines of encoding report: revision, node and transcoded fields with encodings.
+    encoding_report: Vec<String>,
+    /// Mercurial `.gitattributes` of revisions, merged into generated one.
+    gitattributes: HashMap<usize, Rc<Vec<u8>>>,
+    /// Renamed paths modified by exported changeset, see `renamed_path`.
+    renamed: filter::RenamedPaths,
+}
+
+/// Subrepositories of parent and changeset.
+type SubreposChange = (Rc<Subrepos>, Rc<Subrepos>);
+
+struct TagOrigin {
+    tagger: String,
+    message: String,
+}
+
+impl ExportState<'_> {
+    fn new(
+        brmap: HashMap<String, String>,
+        lfs_objects: Option<PathBuf>,
+        lfs_files: Option<LfsFiles>,
+        responses: Option<Responses>,
+        notes_exist: bool,
+    ) -> Self {
+        Self {
+            brmap,
+            tag_origins: HashMap::new(),
+            subrepos: HashMap::new(),
+            subrepo_commits: HashMap::new(),
+            inline_subrepos: HashMap::new(),
+            lfs_objects,
+            lfs_paths: lfs_files.map(|x| x.paths),
+            survivors: Vec::new(),
+            exported_parents: Vec::new(),
+            redaction_report: Vec::new(),
+            responses,
+            commit_ids: HashMap::new(),
+            notes_exist,
+            commit_mismatches: Vec::new(),
+            encoding_report: Vec::new(),
+            gitattributes: HashMap::new(),
+            renamed: filter::RenamedPaths::default(),
+        }
+    }
+
+    /// Saves Git LFS paths and reports of import to target.
+    fn save_reports(self, target: &dyn TargetRepository) -> Result<(), ErrorKind> {
+        if let Some(paths) = self.lfs_paths {
+            target.save_lfs_files(&LfsFiles { paths })?;
+        }
+        if !self.redaction_report.is_empty() {
+            target.save_redaction_report(&self.redaction_report)?;
+        }
+        if !self.commit_mismatches.is_empty() {
+            eprintln!(
+                "{} commits differ from original Git commits of hg-git",
+                self.commit_mismatches.len()
+            );
+            target.save_hg_git_report(&self.commit_mismatches)?;
+        }
+        if !self.encoding_report.is_empty() {
+            target.save_encoding_report(&self.encoding_report)?;
+        }
+        Ok(())
+    }
+
+    /// Remembers changeset which added each `.hgtags` line seen first time.
+    fn add_tag_origins(&mut self, hgtags: &[u8], tagger: &str, message: &str) {
+        for line in to_str(hgtags).lines() {
+            let Some((node, name)) = line.split_once(' ') else {
+                continue;
+            };
+            let Some(node) = NodeId::from_hex(node) else {
+                continue;
+            };
+            self.tag_origins
+                .entry((name.into(), node))
+                .or_insert_with(|| TagOrigin {
+                    tagger: tagger.into(),
+                    message: message.into(),
+                });
+        }
+    }
+}
+
+const HG_DEFAULT_BRANCH: &str = "default";
+
+/// Git timezone of Mercurial offset in seconds west of UTC.
+fn git_timezone(offset: i32) -> String {
+    format!("{:+03}{:02}", -offset / 3600, ((-offset % 3600) / 60))
+}
+
+/// Reads `.hg/git-mapfile` of hg-git with "git-commit changeset-node" lines,
+/// missing file gives empty map.
+fn load_git_mapfile(path: &Path) -> Result<HashMap<NodeId, String>, ErrorKind> {
+    let mapfile = path.join(".hg").join("git-mapfile");
+    if !mapfile.exists() {
+        return Ok(HashMap::new());
+    }
+    Ok(read_file(mapfile)?
+        .lines()
+        .filter_map(|line| {
+            let (commit, node) = line.split_once(' ')?;
+            Some((NodeId::from_hex(node.trim())?, commit.to_string()))
+        })
+        .collect())
+}
+
+/// Temporary ref for commits on named branches in bookmarks only mode, removed after import.
+const BOOKMARKS_ONLY_REF: &str = "refs/hg/bookmarks-only";
+
+/// Changeset extras exported by `provenance` option and their trailers.
+const PROVENANCE_EXTRAS: [(&str, &str); 5] = [
+    ("source", "Hg-Graft-Source"),
+    ("transplant_source", "Hg-Transplant-Source"),
+    ("rebase_source", "Hg-Rebase-Source"),
+    ("amend_source", "Hg-Amend-Source"),
+    ("convert_revision", "Hg-Convert-Revision"),
+];
+
+/// Parents of changeset as nearest exported ancestors, second one is `None`
+/// if it is the same as the first or its ancestor by exported `parents`, so
+/// merge with dropped commits does not become trivial. Without `survivors`
+/// (commits are never dropped) parents are returned as is.
+fn exported_parents(
+    (p1, p2): (Option<Revision>, Option<Revision>),
+    survivors: &[Option<usize>],
+    parents: &[(Option<usize>, Option<usize>)],
+) -> (Option<usize>, Option<usize>) {
+    let survivor = |parent: Option<Revision>| {
+        let parent = usize::from(parent?);
+        survivors.get(parent).copied().unwrap_or(Some(parent))
+    };
+    let (hg_p1, hg_p2) = (p1.map(usize::from), p2.map(usize::from));
+    let (p1, p2) = (survivor(p1), survivor(p2));
+    // Mercurial merges never have second parent as ancestor of the first one,
+    // so ancestry is checked only when some parent is dropped.
+    let dropped = (p1, p2) != (hg_p1, hg_p2);
+    match (p1, p2) {
+        _ if p1 == p2 => (p1, None),
+        (Some(p1), Some(p2)) if dropped && is_ancestor(p2, p1, parents) => (Some(p1), None),
+        _ => (p1, p2),
+    }
+}
+
+/// Whether exported revision `ancestor` is reachable from `revision` by exported `parents`.
+fn is_ancestor(
+    ancestor: usize,
+    revision: usize,
+    parents: &[(Option<usize>, Option<usize>)],
+) -> bool {
+    let mut visited = HashSet::new();
+    let mut stack = vec![revision];
+    while let Some(revision) = stack.pop() {
+        if revision == ancestor {
+            return true;
+        }
+        // Ancestors always have lower revision numbers.
+        if revision < ancestor || !visited.insert(revision) {
+            continue;
+        }
+        let (p1, p2) = parents.get(revision).copied().unwrap_or_default();
+        stack.extend(p1.into_iter().chain(p2));
+    }
+    false
+}
+
+/// Subrepositories after changeset, `None` if it changed neither `.hgsub` nor `.hgsubstate`.
+fn changed_subrepos(changeset: &Changeset, parent: &Subrepos) -> Option<Subrepos> {
+    let mut subrepos = parent.clone();
+    let mut changed = false;
+    for file in &changeset.files {
+        let data = file.data.as_deref().map(file_content).unwrap_or_default();
+        match file.path.as_slice() {
+            b".hgsub" => subrepos.sources = subrepo::parse_hgsub(data),
+            b".hgsubstate" => subrepos.revisions = subrepo::parse_hgsubstate(data),
+            _ => continue,
+        }
+        changed = true;
+    }
+    changed.then_some(subrepos)
+}
+
+/// Mercurial branch of changeset by its extras, `default` if it is not set.
+fn changeset_branch(extra: &HashMap<Vec<u8>, Vec<u8>>) -> &[u8] {
+    extra
+        .get(b"branch".as_slice())
+        .map_or(HG_DEFAULT_BRANCH.as_bytes(), Vec::as_slice)
+}
+
+/// Key of Mercurial branch in branch map, default branch is keyed by Git default branch.
+fn hg_branch_key<'b>(branch: &'b str, default_branch: &'b str) -> &'b str {
+    if branch == HG_DEFAULT_BRANCH {
+        default_branch
+    } else {
+        branch
+    }
+}
+
+struct BranchHead {
+    revision: usize,
+    closed: bool,
+    /// Head has children on other branches.
+    merged: bool,
+}
+
+/// Git LFS pointer for content, content is stored in LFS objects of target.
+fn lfs_pointer(data: &[u8], state: &ExportState) -> Result<Vec<u8>, ErrorKind> {
+    let (oid, pointer) = lfs::pointer(data);
+    if let Some(objects) = &state.lfs_objects {
+        lfs::store(objects, &oid, data)?;
+    }
+    Ok(pointer)
+}
+
+fn file_mode(details: &ManifestEntryDetails) -> &'static str {
+    match details {
+        ManifestEntryDetails::File(FileType::Symlink) => "120000",
+        ManifestEntryDetails::File(FileType::Executable) => "100755",
+        ManifestEntryDetails::Tree | ManifestEntryDetails::File(FileType::Regular) => "100644",
     }
 }
 
@@ -431,6 +2252,90 @@ fn sanitize_name(name: &str, prefix: Option<&String>, what: &str) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn exported_parents_of_dropped_commits() {
+        let parents = |p1: Option<u32>, p2: Option<u32>| (p1.map(Revision), p2.map(Revision));
+        assert_eq!(
+            exported_parents(parents(Some(1), Some(2)), &[], &[]),
+            (Some(1), Some(2))
+        );
+        // Revisions 1 and 4 are dropped and survived by 0 and 3,
+        // revision 2 has no exported ancestor, revision 3 is child of 0.
+        let survivors = [Some(0), Some(0), None, Some(3), Some(3)];
+        let exported = [
+            (None, None),
+            (Some(0), None),
+            (None, None),
+            (Some(0), None),
+            (Some(3), None),
+        ];
+        let exported_parents =
+            |p1, p2| super::exported_parents(parents(p1, p2), &survivors, &exported);
+        assert_eq!(exported_parents(Some(1), Some(3)), (Some(0), Some(3)));
+        assert_eq!(exported_parents(Some(0), Some(1)), (Some(0), None));
+        assert_eq!(exported_parents(Some(2), Some(3)), (None, Some(3)));
+        assert_eq!(exported_parents(Some(2), None), (None, None));
+        assert_eq!(exported_parents(Some(5), None), (Some(5), None));
+        // Second parent survived by ancestor of the first one gives no merge.
+        assert_eq!(exported_parents(Some(4), Some(1)), (Some(3), None));
+        assert_eq!(exported_parents(Some(1), Some(4)), (Some(0), Some(3)));
+    }
+
+    #[test]
+    fn default_branch_of_changeset() {
+        let mut extra = HashMap::new();
+        assert_eq!(changeset_branch(&extra), b"default");
+        assert_eq!(hg_branch_key("default", "master"), "master");
+        extra.insert(b"branch".to_vec(), b"stable".to_vec());
+        assert_eq!(changeset_branch(&extra), b"stable");
+        assert_eq!(hg_branch_key("stable", "master"), "stable");
+    }
+
+    #[test]
+    fn trailers_of_default_branch_changeset() {
+        let trailers = message::Trailers::new("Hg-Branch: {branch}").unwrap();
+        let extra = HashMap::new();
+        let branch = to_str(changeset_branch(&extra));
+        let values = message::TrailerValues {
+            node: "0123456789abcdef0123456789abcdef01234567",
+            revision: 0,
+            branch: &branch,
+        };
+        assert_eq!(
+            trailers.append("Fix bug\n", &values),
+            "Fix bug\n\nHg-Branch: default"
+        );
+    }
+
+    #[test]
+    fn tag_origins_from_first_changeset() {
+        let node = "0123456789abcdef0123456789abcdef01234567";
+        let mut state = ExportState::new(HashMap::new(), None, None, None, false);
+        state.add_tag_origins(
+            format!("{} 1.0\n", node).as_bytes(),
+            "A <a@b.c> 1 +0000",
+            "tag 1.0",
+        );
+        state.add_tag_origins(
+            format!("{} 1.0\n{} 1.1 rc\n", node, node).as_bytes(),
+            "B <b@b.c> 2 +0000",
+            "tag 1.1 rc",
+        );
+        let node = NodeId::from_hex(node).unwrap();
+        assert_eq!(state.tag_origins[&("1.0".into(), node)].message, "tag 1.0");
+        assert_eq!(
+            state.tag_origins[&("1.1 rc".into(), node)].tagger,
+            "B <b@b.c> 2 +0000"
+        );
+    }
+
+    #[test]
+    fn git_timezones() {
+        assert_eq!(git_timezone(0), "+0000");
+        assert_eq!(git_timezone(-7200), "+0200");
+        assert_eq!(git_timezone(18000), "-0500");
+    }
+
     #[test]
     fn sanitize_branchnames() {
         assert_eq!(&sanitize_branchname("normal", None, false), "normal");
@@ -2,22 +2,28 @@
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
-use std::{collections::HashMap, io, path::Path, time::Instant};
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use indicatif::HumanDuration;
 use structopt::StructOpt;
 use tracing::info;
 use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
 
 use hg_git_fast_import::{
+    check::check,
     config::RepositoryConfig,
     env::Environment,
     git::{GitTargetRepository, StdoutTargetRepository},
     multi::multi2git,
     read_file,
     single::hg2git,
-    tools::build_marks,
+    tools::{build_marks, generate_mappings, preview_messages},
 };
 
 mod cli;
@@ -143,6 +149,79 @@ fn main() -> Result<()> {
                 !args.no_backup,
             )?;
         }
+        PreviewMessages {
+            hg_repo,
+            config,
+            ignore_unknown_requirements,
+        } => {
+            let config_str =
+                read_file(&config).with_context(|| format!("Cannot read config {:?}", config))?;
+            let config: RepositoryConfig = toml::from_str(&config_str)
+                .with_context(|| format!("Cannot parse config {:?}", config))?;
+            let changed = preview_messages(
+                hg_repo,
+                &config,
+                ignore_unknown_requirements,
+                &mut io::stdout().lock(),
+            )?;
+            eprintln!("{} messages would be rewritten.", changed);
+        }
+        Check {
+            hg_repo,
+            config,
+            authors,
+            max_file_size,
+            json,
+            fix_wrong_branchname,
+            ignore_unknown_requirements,
+        } => {
+            let config = load_config(config.as_ref())?;
+            let env = tool_environment(authors.as_ref(), fix_wrong_branchname)?;
+            let report = check(
+                hg_repo,
+                &config,
+                &env,
+                ignore_unknown_requirements,
+                max_file_size,
+            )?;
+            let mut stdout = io::stdout().lock();
+            if json {
+                report.write_json(&mut stdout)?;
+            } else {
+                report.write_text(&mut stdout)?;
+            }
+            eprintln!(
+                "{} errors, {} warnings.",
+                report.errors(),
+                report.warnings()
+            );
+            if report.errors() > 0 {
+                bail!("Import would fail with {} errors", report.errors());
+            }
+        }
+        GenerateMappings {
+            hg_repo,
+            config,
+            authors,
+            authors_output,
+            branches_output,
+            ignore_unknown_requirements,
+        } => {
+            let config = load_config(config.as_ref())?;
+            let env = tool_environment(authors.as_ref(), false)?;
+            let mappings = generate_mappings(hg_repo, &config, &env, ignore_unknown_requirements)?;
+            for (template, output) in [
+                (&mappings.authors, authors_output),
+                (&mappings.branches, branches_output),
+            ] {
+                match output {
+                    Some(output) => std::fs::write(&output, template)
+                        .with_context(|| format!("Cannot write {:?}", output))?,
+                    None => println!("{}", template),
+                }
+            }
+            eprintln!("{} users must be mapped by hand.", mappings.todo);
+        }
     }
 
     Ok(())
@@ -180,6 +259,34 @@ fn load_environment(common: &Common) -> Result<Environment, anyhow::Error> {
     })
 }
 
+/// Repository config from file, default config without it.
+fn load_config(config: Option<&PathBuf>) -> Result<RepositoryConfig, anyhow::Error> {
+    config.map_or_else(
+        || Ok(RepositoryConfig::default()),
+        |x| {
+            let config_str = read_file(x).with_context(|| format!("Cannot read config {:?}", x))?;
+            toml::from_str(&config_str).with_context(|| format!("Cannot parse config {:?}", x))
+        },
+    )
+}
+
+/// Environment of tools which read Mercurial repository only.
+fn tool_environment(
+    authors: Option<&PathBuf>,
+    fix_wrong_branchname: bool,
+) -> Result<Environment, anyhow::Error> {
+    Ok(Environment {
+        no_clean_closed_branches: false,
+        authors: authors.map(load_authors).transpose()?,
+        clean: false,
+        cron: false,
+        target_push: false,
+        target_pull: false,
+        source_pull: false,
+        fix_wrong_branchname,
+    })
+}
+
 fn load_authors(p: impl AsRef<Path>) -> Result<HashMap<String, String>, anyhow::Error> {
     info!("Loading authors");
     let authors_str =
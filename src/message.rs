@@ -0,0 +1,183 @@
+use std::borrow::Cow;
+
+use regex::Regex;
+
+use crate::config::MessageRewrite;
+
+/// Regex rewrite rules for commit messages, applied in order.
+#[derive(Debug, Default)]
+pub struct Rewrites {
+    rules: Vec<(Regex, String)>,
+}
+
+impl Rewrites {
+    /// Compiles rules, wrong regex is returned as error.
+    pub fn new(rewrites: &[MessageRewrite]) -> Result<Self, String> {
+        let rules = rewrites
+            .iter()
+            .map(|rewrite| {
+                Regex::new(&rewrite.regex)
+                    .map(|regex| (regex, rewrite.replacement.clone()))
+                    .map_err(|_| rewrite.regex.clone())
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Message after all rules, replacement may refer to groups as `$1` or `${name}`.
+    pub fn apply<'m>(&self, message: &'m str) -> Cow<'m, str> {
+        let mut result = Cow::Borrowed(message);
+        for (regex, replacement) in &self.rules {
+            if let Cow::Owned(rewritten) = regex.replace_all(&result, replacement.as_str()) {
+                result = Cow::Owned(rewritten);
+            }
+        }
+        result
+    }
+}
+
+/// Changeset values available in trailers template.
+pub struct TrailerValues<'v> {
+    pub node: &'v str,
+    pub revision: usize,
+    pub branch: &'v str,
+}
+
+/// Template of trailers appended to commit messages, e.g. `Hg-Node: {node}`.
+#[derive(Debug)]
+pub struct Trailers {
+    template: String,
+}
+
+impl Trailers {
+    const PLACEHOLDERS: [&'static str; 4] = ["node", "short_node", "revision", "branch"];
+
+    /// Checks placeholders of template, unknown one is returned as error.
+    pub fn new(template: &str) -> Result<Self, String> {
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed placeholder in '{}'", template))?;
+            let name = &rest[start + 1..start + end];
+            if !Self::PLACEHOLDERS.contains(&name) {
+                return Err(format!("unknown placeholder '{{{}}}'", name));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        let template = template.trim().to_string();
+        if template.is_empty() {
+            return Err("template is empty".into());
+        }
+        Ok(Self { template })
+    }
+
+    /// Message with rendered trailers, separated by blank line unless
+    /// last paragraph of message already consists of trailers.
+    pub fn append(&self, message: &str, values: &TrailerValues) -> String {
+        let trailers = self
+            .template
+            .replace("{node}", values.node)
+            .replace("{short_node}", &values.node[..12.min(values.node.len())])
+            .replace("{revision}", &values.revision.to_string())
+            .replace("{branch}", values.branch);
+        append_trailers(message, &trailers)
+    }
+}
+
+/// Message with trailers as last paragraph, separated by blank line unless
+/// last paragraph of message already consists of trailers.
+pub fn append_trailers(message: &str, trailers: &str) -> String {
+    let message = message.trim_end();
+    if message.is_empty() {
+        trailers.to_string()
+    } else if has_trailers(message) {
+        format!("{}\n{}", message, trailers)
+    } else {
+        format!("{}\n\n{}", message, trailers)
+    }
+}
+
+/// Last paragraph consists of `Key: value` or `(cherry picked from commit ..)`
+/// lines and is not the only one.
+fn has_trailers(message: &str) -> bool {
+    let Some((_, last)) = message.rsplit_once("\n\n") else {
+        return false;
+    };
+    last.lines().all(|line| {
+        line.starts_with("(cherry picked from commit ")
+            || line.split_once(": ").is_some_and(|(key, _)| {
+                !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn rewrite_messages() {
+        let rewrites = Rewrites::new(&[
+            MessageRewrite {
+                regex: r"https://bugs\.example\.com/show_bug\.cgi\?id=(\d+)".into(),
+                replacement: "#$1".into(),
+            },
+            MessageRewrite {
+                regex: r"(^|\W)#(\d+)\b".into(),
+                replacement: "${1}PROJ-$2".into(),
+            },
+        ])
+        .unwrap();
+        assert_eq!(
+            rewrites.apply("Fix #12 (see https://bugs.example.com/show_bug.cgi?id=34)"),
+            "Fix PROJ-12 (see PROJ-34)"
+        );
+        assert!(matches!(rewrites.apply("No issues"), Cow::Borrowed(_)));
+        assert!(Rewrites::new(&[MessageRewrite {
+            regex: "(".into(),
+            replacement: "".into(),
+        }])
+        .is_err());
+    }
+
+    #[test]
+    fn append_trailers() {
+        let trailers =
+            Trailers::new("Hg-Node: {node}\nHg-Branch: {branch}\nHg-Rev: {revision}\n").unwrap();
+        let values = TrailerValues {
+            node: "0123456789abcdef0123456789abcdef01234567",
+            revision: 42,
+            branch: "stable",
+        };
+        let expected_trailers =
+            "Hg-Node: 0123456789abcdef0123456789abcdef01234567\nHg-Branch: stable\nHg-Rev: 42";
+        assert_eq!(
+            trailers.append("Fix bug\n", &values),
+            format!("Fix bug\n\n{}", expected_trailers)
+        );
+        assert_eq!(
+            trailers.append("Fix bug\n\nSigned-off-by: A <a@b.c>", &values),
+            format!("Fix bug\n\nSigned-off-by: A <a@b.c>\n{}", expected_trailers)
+        );
+        assert_eq!(
+            Trailers::new("Hg: {short_node}")
+                .unwrap()
+                .append("", &values),
+            "Hg: 0123456789ab"
+        );
+        assert_eq!(
+            trailers.append(
+                "Backport\n\n(cherry picked from commit 0123456789abcdef0123456789abcdef01234567)",
+                &values
+            ),
+            format!(
+                "Backport\n\n(cherry picked from commit 0123456789abcdef0123456789abcdef01234567)\n{}",
+                expected_trailers
+            )
+        );
+        assert!(Trailers::new("Hg-User: {user}").is_err());
+        assert!(Trailers::new("Hg-Node: {node").is_err());
+    }
+}
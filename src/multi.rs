@@ -7,7 +7,7 @@ use std::{
 use indicatif::{HumanDuration, ProgressBar, ProgressStyle};
 use tracing::{debug, info};
 
-use super::{config, env, MercurialRepo, RepositorySavedState, TargetRepository};
+use super::{config, env, ExportState, MercurialRepo, RepositorySavedState, TargetRepository};
 use crate::error::ErrorKind;
 use crate::git::GitTargetRepository;
 
@@ -36,6 +36,28 @@ pub fn multi2git<P: AsRef<Path>>(
 
     let config_path = config_filename.as_ref().parent();
 
+    for repo in &multi_config.repositories {
+        if let Some(problem) = verify.then(|| repo.config.verify_unsupported()).flatten() {
+            return Err(ErrorKind::VerifyUnsupported(format!(
+                "{:?}: {}",
+                repo.path_hg, problem
+            )));
+        }
+    }
+
+    // Each repository writes its own root `.gitmodules`, merge of them would conflict.
+    let with_subrepos: Vec<_> = multi_config
+        .repositories
+        .iter()
+        .filter(|repo| repo.config.subrepos.is_some())
+        .map(|repo| format!("{:?}", repo.path_hg))
+        .collect();
+    if with_subrepos.len() > 1 {
+        return Err(ErrorKind::SubreposOfSeveralRepositories(
+            with_subrepos.join(", "),
+        ));
+    }
+
     for repo in &multi_config.repositories {
         export_repository(
             &config_path,
@@ -127,9 +149,7 @@ fn export_repository(
     };
 
     info!("Verifying heads in repository {:?}", repo.path_hg);
-    if !mercurial_repo.verify_heads(repo.config.allow_unnamed_heads)? {
-        return Err(ErrorKind::VerifyFailure("Verify heads failed".into()));
-    }
+    mercurial_repo.verify_heads()?;
 
     let tip = mercurial_repo.changelog_len()?;
 
@@ -147,11 +167,19 @@ fn export_repository(
 
     git_repo.set_env(env);
 
+    let exported_tags = git_repo.get_exported_tags()?;
+    let lfs_objects = git_repo.lfs_objects_path();
+    let lfs_files = git_repo.get_lfs_files()?;
+    let notes_exist = match &repo.config.notes {
+        Some(notes) => git_repo.reference_exists(&notes.reference)?,
+        None => false,
+    };
     let mut errors = None;
     let mut counter: usize = 0;
-    let from_tag = {
-        let (output, saved_state, default_branch) =
+    let (from_tag, exported_tags, state) = {
+        let (output, responses, saved_state, default_branch) =
             git_repo.start_import(git_active_branches, repo.config.default_branch())?;
+        let exported_tags = saved_state.as_ref().and(exported_tags);
 
         let (from, from_tag) = if let Some(saved_state) = saved_state.as_ref() {
             match saved_state {
@@ -163,7 +191,13 @@ fn export_repository(
             (0, 0)
         };
 
-        let mut brmap = repo.config.branches.clone().unwrap_or_default();
+        let mut state = ExportState::new(
+            repo.config.branches.clone().unwrap_or_default(),
+            lfs_objects,
+            saved_state.as_ref().and(lfs_files),
+            responses,
+            notes_exist,
+        );
 
         info!(
             "Exporting commits from repo: {:?} from {} to {} offset {:?}",
@@ -188,7 +222,7 @@ fn export_repository(
             match mercurial_repo.export_commit(
                 &mut changeset,
                 counter,
-                &mut brmap,
+                &mut state,
                 output,
                 &default_branch,
             ) {
@@ -200,6 +234,7 @@ fn export_repository(
             }
         }
 
+        let mut tags = None;
         if errors.is_none() {
             if show_progress_bar {
                 progress_bar.finish_with_message(format!(
@@ -211,11 +246,30 @@ fn export_repository(
                 ));
             }
 
-            counter = mercurial_repo.export_tags(from_tag..to, counter, output)?;
+            counter =
+                mercurial_repo.export_heads(to, counter, &mut state, output, &default_branch)?;
+            counter = mercurial_repo.export_bookmarks(
+                to,
+                counter,
+                &mut state,
+                output,
+                &default_branch,
+            )?;
+            let (progress, exported_tags) = mercurial_repo.export_tags(
+                from_tag..to,
+                exported_tags,
+                counter,
+                &mut state,
+                output,
+            )?;
+            counter = progress;
+            tags = Some(exported_tags);
         }
-        from_tag
+        (from_tag, tags, state)
     };
 
+    state.save_reports(&git_repo)?;
+
     if let Some((error, at)) = errors {
         if at > 0 {
             let at = at as usize;
@@ -236,13 +290,20 @@ fn export_repository(
         to + offset,
         to + offset,
     ))?;
+    if let Some(exported_tags) = exported_tags {
+        git_repo.save_exported_tags(&exported_tags)?;
+    }
 
     git_repo.finish()?;
 
     if verify {
         git_repo.verify(
-            mercurial_repo.path().to_str().unwrap(),
+            repo.config
+                .verified_path(mercurial_repo.path())
+                .to_str()
+                .unwrap(),
             repo.config.path_prefix.as_ref().map(|x| &x[..]),
+            &repo.config.verify_excluded(),
         )?;
     }
 
@@ -0,0 +1,211 @@
+use std::collections::HashSet;
+
+use regex::bytes::Regex;
+use sha1::{Digest, Sha1};
+
+use crate::{config::Redaction, filter::PathFilter};
+
+const DEFAULT_REPLACEMENT: &str = "***REMOVED***";
+
+enum Action {
+    Replace(Regex, Vec<u8>),
+    Placeholder(Vec<u8>),
+    DropBlobs(HashSet<String>),
+}
+
+struct Rule {
+    paths: PathFilter,
+    action: Action,
+}
+
+/// Rules which rewrite blob content during export, applied in order.
+#[derive(Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+/// Result of rules which changed blob.
+#[derive(Debug, PartialEq)]
+pub struct Redacted {
+    /// New content, `None` if blob is dropped.
+    pub data: Option<Vec<u8>>,
+    /// Applied actions, for report.
+    pub actions: Vec<&'static str>,
+}
+
+impl Rules {
+    /// Compiles rules, wrong rule is returned as error description.
+    pub fn new(redactions: &[Redaction]) -> Result<Self, String> {
+        let rules = redactions
+            .iter()
+            .map(|redaction| -> Result<Rule, String> {
+                let paths = PathFilter::new(&redaction.paths, &[])
+                    .map_err(|pattern| format!("wrong path pattern '{}'", pattern))?;
+                let replacement = redaction
+                    .replacement
+                    .as_deref()
+                    .unwrap_or(DEFAULT_REPLACEMENT)
+                    .as_bytes()
+                    .to_vec();
+                let action = match redaction {
+                    Redaction {
+                        text: Some(text),
+                        regex: None,
+                        placeholder: None,
+                        blobs,
+                        ..
+                    } if blobs.is_empty() => {
+                        Action::Replace(Regex::new(&regex::escape(text)).unwrap(), replacement)
+                    }
+                    Redaction {
+                        text: None,
+                        regex: Some(regex),
+                        placeholder: None,
+                        blobs,
+                        ..
+                    } if blobs.is_empty() => Action::Replace(
+                        Regex::new(regex).map_err(|e| format!("wrong regex '{}': {}", regex, e))?,
+                        replacement,
+                    ),
+                    Redaction {
+                        text: None,
+                        regex: None,
+                        placeholder: Some(placeholder),
+                        blobs,
+                        ..
+                    } if blobs.is_empty() => Action::Placeholder(placeholder.as_bytes().to_vec()),
+                    Redaction {
+                        text: None,
+                        regex: None,
+                        placeholder: None,
+                        blobs,
+                        ..
+                    } if !blobs.is_empty() => Action::DropBlobs(
+                        blobs.iter().map(|blob| blob.to_ascii_lowercase()).collect(),
+                    ),
+                    _ => {
+                        return Err(
+                            "exactly one of text, regex, placeholder or blobs is expected".into(),
+                        )
+                    }
+                };
+                Ok(Rule { paths, action })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Applies rules to blob of file, `None` if nothing is changed.
+    pub fn apply(&self, path: &str, data: &[u8]) -> Option<Redacted> {
+        let mut result: Option<Vec<u8>> = None;
+        let mut actions = vec![];
+        let mut blob_id = None;
+        for rule in &self.rules {
+            if !rule.paths.is_match(path) {
+                continue;
+            }
+            let current = result.as_deref().unwrap_or(data);
+            match &rule.action {
+                Action::Replace(regex, replacement) => {
+                    if regex.is_match(current) {
+                        result = Some(regex.replace_all(current, replacement.as_slice()).into());
+                        actions.push("text replaced");
+                    }
+                }
+                Action::Placeholder(placeholder) => {
+                    result = Some(placeholder.clone());
+                    actions.push("placeholder");
+                }
+                Action::DropBlobs(blobs) => {
+                    let blob_id = blob_id.get_or_insert_with(|| git_blob_id(data));
+                    if blobs.contains(blob_id) {
+                        actions.push("blob dropped");
+                        return Some(Redacted {
+                            data: None,
+                            actions,
+                        });
+                    }
+                }
+            }
+        }
+        result.map(|data| Redacted {
+            data: Some(data),
+            actions,
+        })
+    }
+}
+
+/// SHA-1 of blob in Git.
+pub fn git_blob_id(data: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", data.len()));
+    hasher.update(data);
+    hasher
+        .finalize()
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn redact_blobs() {
+        let rules = Rules::new(&[
+            Redaction {
+                paths: vec!["*.ini".into()],
+                text: Some("hunter2".into()),
+                ..Default::default()
+            },
+            Redaction {
+                regex: Some("AKIA[0-9A-Z]{4}".into()),
+                replacement: Some("<key>".into()),
+                ..Default::default()
+            },
+            Redaction {
+                paths: vec!["dumps/".into()],
+                placeholder: Some("removed dump\n".into()),
+                ..Default::default()
+            },
+            Redaction {
+                blobs: vec!["D97C5EADA5D8C52079031EEF0107A4430A9617C5".into()],
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+        assert_eq!(
+            git_blob_id(b"secret\n"),
+            "d97c5eada5d8c52079031eef0107a4430a9617c5"
+        );
+        assert_eq!(rules.apply("a.txt", b"hunter2"), None);
+        assert_eq!(
+            rules.apply("conf/a.ini", b"password=hunter2 key=AKIAABCD\n"),
+            Some(Redacted {
+                data: Some(b"password=***REMOVED*** key=<key>\n".to_vec()),
+                actions: vec!["text replaced", "text replaced"],
+            })
+        );
+        assert_eq!(
+            rules.apply("dumps/db.sql", b"data"),
+            Some(Redacted {
+                data: Some(b"removed dump\n".to_vec()),
+                actions: vec!["placeholder"],
+            })
+        );
+        assert_eq!(
+            rules.apply("any", b"secret\n"),
+            Some(Redacted {
+                data: None,
+                actions: vec!["blob dropped"],
+            })
+        );
+        assert!(Rules::new(&[Redaction::default()]).is_err());
+    }
+}
@@ -5,7 +5,7 @@ use tracing::{debug, info};
 
 use crate::error::ErrorKind;
 
-use super::{config, env, MercurialRepo, RepositorySavedState, TargetRepository};
+use super::{config, env, ExportState, MercurialRepo, RepositorySavedState, TargetRepository};
 
 pub fn hg2git<P: AsRef<Path>>(
     repourl: P,
@@ -19,6 +19,13 @@ pub fn hg2git<P: AsRef<Path>>(
     debug!("Config: {:?}", repository_config);
     debug!("Environment: {:?}", env);
 
+    if let Some(problem) = verify
+        .then(|| repository_config.verify_unsupported())
+        .flatten()
+    {
+        return Err(ErrorKind::VerifyUnsupported(problem));
+    }
+
     let repo = MercurialRepo::open_with_pull(
         repourl.as_ref(),
         repository_config,
@@ -26,9 +33,7 @@ pub fn hg2git<P: AsRef<Path>>(
         env,
     )?;
 
-    if !repo.verify_heads(repository_config.allow_unnamed_heads)? {
-        return Err(ErrorKind::VerifyFailure("Verify heads failed".into()));
-    };
+    repo.verify_heads()?;
 
     let tip = repo.changelog_len()?;
 
@@ -39,14 +44,28 @@ pub fn hg2git<P: AsRef<Path>>(
     };
 
     debug!("Checking saved state...");
-    let mut brmap = repository_config.branches.clone().unwrap_or_default();
+    let lfs_objects = target.lfs_objects_path();
     let mut counter: usize = 0;
     let offset = repository_config.offset.unwrap_or(0);
 
+    let exported_tags = target.get_exported_tags()?;
+    let lfs_files = target.get_lfs_files()?;
+    let notes_exist = match &repository_config.notes {
+        Some(notes) => target.reference_exists(&notes.reference)?,
+        None => false,
+    };
     let mut errors = None;
-    let from_tag = {
-        let (output, saved_state, default_branch) =
+    let (from_tag, exported_tags, state) = {
+        let (output, responses, saved_state, default_branch) =
             target.start_import(git_active_branches, repository_config.default_branch())?;
+        let exported_tags = saved_state.as_ref().and(exported_tags);
+        let mut state = ExportState::new(
+            repository_config.branches.clone().unwrap_or_default(),
+            lfs_objects,
+            saved_state.as_ref().and(lfs_files),
+            responses,
+            notes_exist,
+        );
 
         let (from, from_tag) = if let Some(saved_state) = saved_state.as_ref() {
             match saved_state {
@@ -75,7 +94,7 @@ pub fn hg2git<P: AsRef<Path>>(
                 progress_bar.set_message(format!("{:6}/{}", changeset.revision.0, to));
             }
 
-            match repo.export_commit(&mut changeset, counter, &mut brmap, output, &default_branch) {
+            match repo.export_commit(&mut changeset, counter, &mut state, output, &default_branch) {
                 Ok(progress) => counter = progress,
                 x => {
                     errors = Some((x, changeset.revision.0));
@@ -84,6 +103,7 @@ pub fn hg2git<P: AsRef<Path>>(
             }
         }
 
+        let mut tags = None;
         if errors.is_none() {
             if show_progress_bar {
                 progress_bar.finish_with_message(format!(
@@ -93,12 +113,19 @@ pub fn hg2git<P: AsRef<Path>>(
                 ));
             }
 
-            counter = repo.export_tags(from_tag..to, counter, output)?;
+            counter = repo.export_heads(to, counter, &mut state, output, &default_branch)?;
+            counter = repo.export_bookmarks(to, counter, &mut state, output, &default_branch)?;
+            let (progress, exported_tags) =
+                repo.export_tags(from_tag..to, exported_tags, counter, &mut state, output)?;
+            counter = progress;
+            tags = Some(exported_tags);
         }
 
-        from_tag
+        (from_tag, tags, state)
     };
 
+    state.save_reports(&*target)?;
+
     if let Some((error, at)) = errors {
         if at > 0 {
             let at = at as usize;
@@ -118,13 +145,20 @@ pub fn hg2git<P: AsRef<Path>>(
         to + offset,
         to + offset,
     ))?;
+    if let Some(exported_tags) = exported_tags {
+        target.save_exported_tags(&exported_tags)?;
+    }
 
     target.finish()?;
 
     if verify {
         target.verify(
-            repourl.as_ref().to_str().unwrap(),
+            repository_config
+                .verified_path(repourl.as_ref())
+                .to_str()
+                .unwrap(),
             repository_config.path_prefix.as_ref().map(|x| &x[..]),
+            &repository_config.verify_excluded(),
         )?;
     }
 
@@ -0,0 +1,245 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    rc::Rc,
+    sync::Arc,
+};
+
+use hg_parser::Revision;
+use tracing::warn;
+
+use crate::{
+    changelog::{Changelog, NodeId},
+    config::SubrepoConfig,
+    error::ErrorKind,
+    file_mode, read_file, to_str, MercurialRepo,
+};
+
+/// Subrepositories of revision, read from `.hgsub` and `.hgsubstate`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Subrepos {
+    /// Path of subrepository to its source.
+    pub sources: BTreeMap<String, String>,
+    /// Path of subrepository to pinned revision.
+    pub revisions: BTreeMap<String, String>,
+}
+
+/// Kind of subrepository, taken from `[kind]` prefix of source.
+#[derive(Debug, PartialEq)]
+pub enum Source<'a> {
+    Hg(&'a str),
+    Git(&'a str),
+    Svn(&'a str),
+}
+
+impl<'a> Source<'a> {
+    pub fn parse(source: &'a str) -> Self {
+        if let Some(url) = source.strip_prefix("[git]") {
+            Self::Git(url)
+        } else if let Some(url) = source.strip_prefix("[svn]") {
+            Self::Svn(url)
+        } else {
+            Self::Hg(source.strip_prefix("[hg]").unwrap_or(source))
+        }
+    }
+
+    pub fn url(&self) -> &'a str {
+        match self {
+            Self::Hg(url) | Self::Git(url) | Self::Svn(url) => url,
+        }
+    }
+}
+
+/// Parses `.hgsub`, `[subpaths]` rewriting is not supported.
+pub fn parse_hgsub(hgsub: &[u8]) -> BTreeMap<String, String> {
+    let mut result = BTreeMap::new();
+    let mut subpaths = false;
+    for line in to_str(hgsub).lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            subpaths = line == "[subpaths]";
+            if subpaths {
+                warn!("[subpaths] section of .hgsub is not supported");
+            }
+            continue;
+        }
+        if subpaths {
+            continue;
+        }
+        if let Some((path, source)) = line.split_once('=') {
+            result.insert(path.trim().into(), source.trim().into());
+        }
+    }
+    result
+}
+
+/// Parses `.hgsubstate`: pinned revision and path on each line.
+pub fn parse_hgsubstate(hgsubstate: &[u8]) -> BTreeMap<String, String> {
+    to_str(hgsubstate)
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(revision, path)| (path.into(), revision.into()))
+        .collect()
+}
+
+/// Renders `.gitmodules` for subrepositories with URLs given by `url`.
+pub fn gitmodules<F: Fn(&str) -> String>(sources: &BTreeMap<String, String>, url: F) -> Vec<u8> {
+    let mut result = String::new();
+    for (path, source) in sources {
+        result.push_str(&format!(
+            "[submodule \"{}\"]\n\tpath = {}\n\turl = {}\n",
+            path,
+            path,
+            url(source)
+        ));
+    }
+    result.into_bytes()
+}
+
+/// Git commits of Mercurial subrepository imported earlier.
+pub struct SubrepoCommits {
+    changelog: Changelog,
+    marks: HashMap<usize, String>,
+    offset: usize,
+}
+
+impl SubrepoCommits {
+    /// Reads subrepository changelog and marks of its Git repository,
+    /// `None` if they are not configured.
+    pub fn open(config: &SubrepoConfig) -> Result<Option<Self>, ErrorKind> {
+        let (Some(path_hg), Some(path_git)) = (&config.path_hg, &config.path_git) else {
+            return Ok(None);
+        };
+        let changelog = Changelog::open(path_hg)?;
+        let marks = read_file(path_git.join(".git").join("hg-git-fast-import.marks"))?
+            .lines()
+            .filter_map(|line| {
+                let (mark, commit) = line.split_once(' ')?;
+                Some((mark.strip_prefix(':')?.parse().ok()?, commit.into()))
+            })
+            .collect();
+        Ok(Some(Self {
+            changelog,
+            marks,
+            offset: config.offset.unwrap_or(0),
+        }))
+    }
+
+    pub fn commit(&self, node: &str) -> Option<&str> {
+        let revision = self.changelog.revision(&NodeId::from_hex(node)?)?;
+        self.marks
+            .get(&(revision + 1 + self.offset))
+            .map(String::as_str)
+    }
+}
+
+/// Files of subrepository revision: path to mode and Mercurial file data.
+pub type SubrepoTree = BTreeMap<Vec<u8>, (&'static str, Arc<[u8]>)>;
+
+/// Mercurial subrepository which is written into parent tree.
+pub struct InlineSubrepo<'a> {
+    repo: MercurialRepo<'a>,
+    /// Trees of pinned revisions.
+    trees: HashMap<usize, Rc<SubrepoTree>>,
+}
+
+impl<'a> InlineSubrepo<'a> {
+    pub fn new(repo: MercurialRepo<'a>) -> Self {
+        Self {
+            repo,
+            trees: HashMap::new(),
+        }
+    }
+
+    /// Tree at node, `None` if subrepository has no such node.
+    ///
+    /// Manifests are not accessible through `hg_parser`, so tree is built
+    /// from the nearest known tree on first parents line by applying changed files.
+    pub fn tree(&mut self, node: &str) -> Result<Option<Rc<SubrepoTree>>, ErrorKind> {
+        let Some(revision) =
+            NodeId::from_hex(node).and_then(|node| self.repo.changelog.revision(&node))
+        else {
+            return Ok(None);
+        };
+        if let Some(tree) = self.trees.get(&revision) {
+            return Ok(Some(tree.clone()));
+        }
+
+        let mut revisions = vec![];
+        let mut tree = SubrepoTree::new();
+        let mut current = Some(revision);
+        while let Some(rev) = current {
+            if let Some(known) = self.trees.get(&rev) {
+                tree = (**known).clone();
+                break;
+            }
+            revisions.push(rev);
+            current = self
+                .repo
+                .inner
+                .range_header_iter(Revision::from(rev as u32).range())
+                .next()
+                .and_then(|header| header.p1)
+                .map(usize::from);
+        }
+
+        for rev in revisions.into_iter().rev() {
+            for changeset in self.repo.inner.range_iter(rev..rev + 1) {
+                for file in changeset.files {
+                    match (file.data, file.manifest_entry) {
+                        (Some(data), Some(manifest_entry)) => {
+                            tree.insert(file.path, (file_mode(&manifest_entry.details), data));
+                        }
+                        _ => {
+                            tree.remove(&file.path);
+                        }
+                    }
+                }
+            }
+        }
+
+        let tree = Rc::new(tree);
+        self.trees.insert(revision, tree.clone());
+        Ok(Some(tree))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn subrepos_to_gitmodules() {
+        let sources = parse_hgsub(
+            b"# comment\nlib = ../lib\nvendor/git = [git]https://example.com/git.git\n\
+              [subpaths]\n^\\.\\./(.*) = https://example.com/\\1\n",
+        );
+        let revisions = parse_hgsubstate(
+            b"0123456789abcdef0123456789abcdef01234567 lib\n\
+              89abcdef0123456789abcdef0123456789abcdef vendor/git\n",
+        );
+        assert_eq!(
+            revisions.get("lib").map(String::as_str),
+            Some("0123456789abcdef0123456789abcdef01234567")
+        );
+        assert_eq!(
+            Source::parse(&sources["vendor/git"]),
+            Source::Git("https://example.com/git.git")
+        );
+        let gitmodules = gitmodules(&sources, |source| {
+            if source == "../lib" {
+                "https://example.com/lib.git".into()
+            } else {
+                Source::parse(source).url().into()
+            }
+        });
+        assert_eq!(
+            String::from_utf8(gitmodules).unwrap(),
+            "[submodule \"lib\"]\n\tpath = lib\n\turl = https://example.com/lib.git\n\
+             [submodule \"vendor/git\"]\n\tpath = vendor/git\n\turl = https://example.com/git.git\n"
+        );
+    }
+}
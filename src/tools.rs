@@ -5,11 +5,18 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use hg_parser::{ChangesetHeader, MercurialRepository};
+use hg_parser::{ChangesetHeader, MercurialRepository, MercurialRepositoryOptions};
 
+use crate::changelog::Changelog;
+use crate::config::RepositoryConfig;
+use crate::env::Environment;
 use crate::error::ErrorKind;
-use crate::git::GitTargetRepository;
-use crate::{read_file, TargetRepositoryError};
+use crate::git::{GitTargetRepository, DEFAULT_BRANCH};
+use crate::message::Rewrites;
+use crate::{
+    hg_branch_key, read_file, sanitize_branchname, MercurialRepo, TargetRepositoryError,
+    HG_DEFAULT_BRANCH,
+};
 
 use super::{to_str, to_string};
 
@@ -72,6 +79,190 @@ pub fn build_marks<P: AsRef<Path>, S: ::std::hash::BuildHasher>(
     build_marks.process(authors, offset)
 }
 
+/// Writes commit messages changed by `message_rewrites` before and after rewriting.
+///
+/// Nothing is imported, returns number of changed messages.
+pub fn preview_messages<P: AsRef<Path>>(
+    hg_repo: P,
+    config: &RepositoryConfig,
+    ignore_unknown_requirements: bool,
+    output: &mut dyn Write,
+) -> Result<usize, ErrorKind> {
+    let rewrites =
+        Rewrites::new(&config.message_rewrites).map_err(ErrorKind::WrongMessageRewrite)?;
+    let changelog = Changelog::open(hg_repo.as_ref())?;
+    let hg_repo = MercurialRepository::open_with_options(
+        hg_repo,
+        MercurialRepositoryOptions {
+            ignore_unknown_requirements,
+        },
+    )?;
+
+    let mut changed = 0;
+    for (revision, header) in hg_repo.header_iter().enumerate() {
+        let message = to_str(&header.comment);
+        let rewritten = rewrites.apply(&message);
+        if rewritten == message {
+            continue;
+        }
+        let node = changelog
+            .node(revision)
+            .map(|node| node.short())
+            .unwrap_or_default();
+        writeln!(output, "Revision {} ({}):", revision, node)?;
+        for line in message.lines() {
+            writeln!(output, "- {}", line)?;
+        }
+        for line in rewritten.lines() {
+            writeln!(output, "+ {}", line)?;
+        }
+        writeln!(output)?;
+        changed += 1;
+    }
+    Ok(changed)
+}
+
+/// Templates of authors mapping and `[branches]` table of config in toml format.
+pub struct Mappings {
+    pub authors: String,
+    pub branches: String,
+    /// Users which cannot be parsed and must be mapped by hand.
+    pub todo: usize,
+}
+
+/// Commits of user or branch and their first and last dates.
+#[derive(Default)]
+struct Usage {
+    commits: usize,
+    first: Option<(i64, String)>,
+    last: Option<(i64, String)>,
+}
+
+impl Usage {
+    fn add(&mut self, header: &ChangesetHeader) {
+        let time = header.time.timestamp_secs();
+        let date = || header.time.as_chrono().format("%Y-%m-%d").to_string();
+        self.commits += 1;
+        if self.first.as_ref().map_or(true, |(first, _)| time < *first) {
+            self.first = Some((time, date()));
+        }
+        if self.last.as_ref().map_or(true, |(last, _)| time > *last) {
+            self.last = Some((time, date()));
+        }
+    }
+
+    fn comment(&self) -> String {
+        let date = |x: &Option<(i64, String)>| x.as_ref().map(|(_, date)| date.clone());
+        format!(
+            "{} commits, {} - {}",
+            self.commits,
+            date(&self.first).unwrap_or_default(),
+            date(&self.last).unwrap_or_default()
+        )
+    }
+}
+
+/// Line of authors mapping file.
+fn author_line(user: &str, author: &str) -> String {
+    format!("{} = {}\n", toml_string(user), toml_string(author))
+}
+
+/// Generates authors mapping with every Mercurial user and `[branches]` table with
+/// every named branch. Users which are parsed or already mapped by `config` or `env`
+/// are pre-filled, others are commented out with TODO author. Branches are mapped to sanitized names.
+pub fn generate_mappings<P: AsRef<Path>>(
+    hg_repo: P,
+    config: &RepositoryConfig,
+    env: &Environment,
+    ignore_unknown_requirements: bool,
+) -> Result<Mappings, ErrorKind> {
+    let repo = MercurialRepo::open(hg_repo, config, ignore_unknown_requirements, env)?;
+    let mut users: BTreeMap<String, Usage> = BTreeMap::new();
+    let mut branches: BTreeMap<String, Usage> = BTreeMap::new();
+    for header in repo.inner.header_iter() {
+        let decode = |x: &[u8]| {
+            repo.decoder
+                .decode(x)
+                .map_or_else(|_| to_string(x), |(x, _)| x.into_owned())
+        };
+        users.entry(decode(&header.user)).or_default().add(&header);
+        let branch = header
+            .extra
+            .get(b"branch".as_slice())
+            .map_or_else(|| HG_DEFAULT_BRANCH.into(), |x| decode(x));
+        branches.entry(branch).or_default().add(&header);
+    }
+
+    let mut todo = 0;
+    let mut authors = String::from(
+        "# Mapping of Mercurial users to Git authors \"Name <email>\".\n\
+         # Users marked TODO cannot be parsed, uncomment and map them by hand.\n",
+    );
+    for (user, usage) in &users {
+        authors.push('\n');
+        match repo.fixup_user(user) {
+            Ok(author) => {
+                authors.push_str(&format!("# {}\n", usage.comment()));
+                authors.push_str(&author_line(user, &author));
+            }
+            Err(_) => {
+                todo += 1;
+                authors.push_str(&format!("# TODO: {}\n", usage.comment()));
+                authors.push_str(&format!("# {}", author_line(user, "TODO")));
+            }
+        }
+    }
+
+    let default_branch = config.default_branch().unwrap_or(DEFAULT_BRANCH);
+    let mut result = String::from(
+        "# Mercurial branches with proposed Git names, values must include branch_prefix.\n\
+         # Default branch is keyed by Git default branch.\n\
+         [branches]\n",
+    );
+    for (branch, usage) in &branches {
+        let key = hg_branch_key(branch, default_branch);
+        let name = match config.branches.as_ref().and_then(|x| x.get(key)) {
+            Some(name) => name.clone(),
+            None => sanitize_branchname(
+                key,
+                if branch != HG_DEFAULT_BRANCH || config.prefix_default_branch {
+                    config.branch_prefix.as_ref()
+                } else {
+                    None
+                },
+                true,
+            ),
+        };
+        result.push_str(&format!(
+            "\n# {}\n{} = {}\n",
+            usage.comment(),
+            toml_string(key),
+            toml_string(&name)
+        ));
+    }
+
+    Ok(Mappings {
+        authors,
+        branches: result,
+        todo,
+    })
+}
+
+/// Basic toml string, usable as key as well.
+fn toml_string(value: &str) -> String {
+    let mut result = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if c.is_control() => result.push_str(&format!("\\u{:04X}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
 struct BuildMarks<'a> {
     git_repo: GitTargetRepository<'a>,
     git_repo_mapping: HashMap<RevisionHeader, Vec<String>>,
@@ -293,3 +484,21 @@ fn load_git_revlog_lines(stdout: &[u8]) -> (HashMap<RevisionHeader, Vec<String>>
     }
     (result, revlog)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn authors_template_is_readable() {
+        let user = "O'Neil \"Jr\" \\ <a@b.c>\t";
+        let authors: HashMap<String, String> =
+            toml::from_str(&author_line(user, "A <a@b.c>")).unwrap();
+        assert_eq!(authors[user], "A <a@b.c>");
+
+        let template = format!("# {}", author_line(user, "TODO"));
+        let authors: HashMap<String, String> = toml::from_str(&template).unwrap();
+        assert!(authors.is_empty());
+    }
+}